@@ -1,210 +1,1376 @@
 /*
 This file will be used to interact with the database.sqlite file.
 The database.sqlite file will be used to store the data about the downloads and the library of the user.
+The application shares a single connection, owned by the Tauri state, instead of opening one for every query.
+The connection runs in WAL mode with a busy timeout, and the queries run on the blocking thread pool
+so that they never block the async runtime.
 */
 
 use rusqlite::Error as RusqliteError;
-use serde_json::{json, Value};
-
-/// Function to create a database.sqlite file.
-/// It will create 2 tables:
-///    - Downloads:
-///       - name TEXT (name of the torrent)
-///       - game TEXT (name of the game)
-///       - path TEXT (path to the game)
-///       - link TEXT (magnet link of the torrent)
-///       - uploader TEXT (name of the uploader, will be used to search the install instructions)
-///
-///     - Library:
-///       - name TEXT (name of the game)
-///       - path TEXT (path to the game)
-///       - executable TEXT (path to the executable)
-///
+use rusqlite::OptionalExtension;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use log::{trace, debug};
 
-pub fn create_database_sqlite() {
-    // Check if the database file already exists if yes return
-    if std::path::Path::new("database.sqlite").exists() {
-        return;
-    }
-
-    // Attempt to create the database file
-    let conn = match rusqlite::Connection::open("database.sqlite") {
-        Ok(conn) => conn,
-        Err(_) => return,
-    };
-
-    // Attempt to create the downloads table
-    if conn.execute(
-        "CREATE TABLE IF NOT EXISTS downloads (
-            name TEXT,
-            game TEXT,
-            link TEXT,
-            uploader TEXT,
-            path TEXT
-        )",
-        [],
-    ).is_err() {
-        return;
-    }
-
-    // Attempt to create the library table
-    if conn.execute(
-        "CREATE TABLE IF NOT EXISTS library (
-            name TEXT,
-            game TEXT,
-            path TEXT,
-            executable TEXT
-        )",
-        [],
-    ).is_err() {
-        return;
-    }
-}
-
-/// Function to get the all the downloads from the database.
-/// It will return a List of JSON objects containing the name, link and uploader of the torrent.
-/// Returns a Result<Vec<Value>, RusqliteError> to handle potential SQL execution errors.
-///
-/// # Example
-/// ```rust
-/// let downloads = get_downloads().await.unwrap();
-/// ```
+use crate::migrations;
+
+// Time a query waits for a lock held by another connection before failing with SQLITE_BUSY
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Handle to the database.sqlite file.
+/// It is cheap to clone, every clone shares the same connection.
+#[derive(Clone)]
+pub struct Database {
+    conn: Arc<Mutex<rusqlite::Connection>>,
+}
+
+impl Database {
+
+    /// Function to open the database file and to create or update its tables.
+    /// The tables are created and updated by the migrations, see migrations.rs.
+    /// The latest schema has 2 tables:
+    ///    - Downloads:
+    ///       - id INTEGER (primary key of the download, used to refer to the torrent in the torrent manager)
+    ///       - name TEXT (name of the torrent)
+    ///       - game TEXT (name of the game)
+    ///       - path TEXT (directory of the download, resolved from the PATH_TEMPLATE when it is added)
+    ///       - link TEXT (magnet link of the torrent, or path of the imported .torrent file)
+    ///       - uploader TEXT (name of the uploader, will be used to search the install instructions)
+    ///       - status TEXT (state of the download: queued, downloading, paused, seeding, completed, cancelled or error)
+    ///       - priority INTEGER (position of the download in the queue, lower starts first)
+    ///       - download_limit INTEGER (download limit in bytes per second, NULL to use the global one)
+    ///       - upload_limit INTEGER (upload limit in bytes per second, NULL to use the global one)
+    ///       - files TEXT (JSON list of the indices of the files to download, NULL to download all of them)
+    ///       - info_hash TEXT (unique info hash of the torrent, cancelled downloads give it up to a new download of the same torrent)
+    ///       - seeding_policy TEXT (seeding policy of the download, NULL to use the global one)
+    ///       - extra_trackers INTEGER (1 to add the extra trackers to the magnet link, 0 otherwise)
+    ///       - error TEXT (message of the last error of the download, NULL if it has not failed)
+    ///       - attempts INTEGER (number of failed attempts to start the download)
+    ///       - retry_at INTEGER (unix time of the next automatic retry, NULL if it will not be retried)
+    ///       - size INTEGER (total size in bytes of the selected files, NULL until the metadata is known)
+    ///       - bytes_done INTEGER (downloaded bytes, saved periodically while the torrent runs)
+    ///       - created_at INTEGER (unix time at which the download was added)
+    ///       - completed_at INTEGER (unix time at which the download completed, NULL if it has not)
+    ///       - file_names TEXT (JSON list of the paths of the files of the torrent, NULL until the metadata is known)
+    ///       - uploaded_bytes INTEGER (uploaded bytes, saved periodically while the torrent seeds)
+    ///       - seeding_seconds INTEGER (time the torrent has been seeding for, saved periodically while it seeds)
+    ///
+    ///     - Library:
+    ///       - id INTEGER (primary key of the library entry)
+    ///       - name TEXT (name of the download the game comes from)
+    ///       - game TEXT (name of the game)
+    ///       - path TEXT (path to the game)
+    ///       - executable TEXT (path to the executable, NULL until the user chooses it)
+    ///       - download_id INTEGER (unique id of the download the game comes from, NULL for the games added by hand)
+    ///
+    /// # Arguments
+    /// * `path` - The path of the database file, it is created if it does not exist.
+    ///
+    /// # Example
+    /// ```rust
+    /// let db = Database::open("database.sqlite").unwrap();
+    /// ```
+    ///
+    pub fn open(path: &str) -> Result<Self, RusqliteError> {
+        trace!("Opening database {}", path);
+
+        // Attempt to open the database file, it is created if it does not exist
+        let mut conn = rusqlite::Connection::open(path)?;
+
+        // WAL lets the readers run while a write is in progress, the busy timeout waits for the other writers
+        let journal_mode: String = conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        debug!("Database journal mode: {}", journal_mode);
+
+        // Attempt to migrate the tables to the latest schema
+        migrations::migrate(&mut conn)?;
+
+        Ok(Self { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    /// Function to run a query on the shared connection.
+    /// The query runs on the blocking thread pool, the connection is locked for the duration of the query.
+    async fn run<T, F>(&self, query: F) -> Result<T, RusqliteError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut rusqlite::Connection) -> Result<T, RusqliteError> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            // A query that panicked can not leave the connection in a broken state, so the lock is recovered
+            let mut conn = conn.lock().unwrap_or_else(|e| e.into_inner());
+            query(&mut conn)
+        })
+        .await;
+
+        match result {
+            Ok(result) => result,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            // The task is only cancelled when the runtime shuts down
+            Err(e) => Err(RusqliteError::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_ABORT),
+                Some(e.to_string()),
+            )),
+        }
+    }
+}
+
+/// Status of a download.
+/// It is stored as text in the status column, the values match the CHECK constraint of the downloads table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadStatus {
+    Queued,
+    Downloading,
+    Paused,
+    Seeding,
+    Completed,
+    Cancelled,
+    Error,
+}
+
+impl DownloadStatus {
+
+    /// Function to get the text stored in the status column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DownloadStatus::Queued => "queued",
+            DownloadStatus::Downloading => "downloading",
+            DownloadStatus::Paused => "paused",
+            DownloadStatus::Seeding => "seeding",
+            DownloadStatus::Completed => "completed",
+            DownloadStatus::Cancelled => "cancelled",
+            DownloadStatus::Error => "error",
+        }
+    }
+}
+
+impl fmt::Display for DownloadStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DownloadStatus {
+    type Err = String;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            "queued" => Ok(DownloadStatus::Queued),
+            "downloading" => Ok(DownloadStatus::Downloading),
+            "paused" => Ok(DownloadStatus::Paused),
+            "seeding" => Ok(DownloadStatus::Seeding),
+            "completed" => Ok(DownloadStatus::Completed),
+            "cancelled" => Ok(DownloadStatus::Cancelled),
+            "error" => Ok(DownloadStatus::Error),
+            _ => Err(format!("Unknown download status: {}", status)),
+        }
+    }
+}
+
+impl ToSql for DownloadStatus {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>, RusqliteError> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for DownloadStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|e: String| FromSqlError::Other(e.into()))
+    }
+}
+
+/// Download of the downloads table.
+/// The id is the primary key of the download and it is used to refer to the torrent in the torrent manager.
+/// The nullable columns are Options, e.g. a NULL limit means that the download only follows the global limits.
 ///
-/// # Returns
+/// # Serialized
 /// ```json
-/// [
-///     {
-///         "name": "Zelda",
-///         "link": "magnet:?xt=urn:btih:...",
-///         "uploader": "Noidea"
-///     },
-///     {
-///         "name": "Mario",
-///         "link": "magnet:?xt=urn:btih:...",
-///         "uploader": "Noidea"
-///     }
-/// ]
-///
-pub async fn get_downloads() -> Result<Vec<Value>, RusqliteError> {
-
-    // Attempt to query the database for the downloads
-    let conn = rusqlite::Connection::open("database.sqlite")?;
-    let mut stmt = conn.prepare("SELECT name, game, link, uploader, path FROM downloads")?;
-    let downloads = stmt
-        .query_map([], |row| {
-            Ok(json!({
-                "name": row.get::<_, String>(0)?,
-                "game": row.get::<_, String>(1)?,
-                "link": row.get::<_, String>(2)?,
-                "uploader": row.get::<_, String>(3)?,
-                "path": row.get::<_, String>(4)?
-            }))
-        })?
-        .collect::<Result<Vec<Value>, RusqliteError>>()?;
-
-    Ok(downloads)
-}
-
-/// Function to add a download to the database.
-/// It will take the name, link and uploader of the torrent and add it to the downloads table.
-/// It will return a Result<(), RusqliteError> to handle potential SQL execution errors.
-///
-/// # Arguments
-/// - name: &str (name of the torrent)
-/// - link: &str (magnet link of the torrent)
-/// - uploader: &str (name of the uploader)
-///
-///
-/// # Returns
-/// ```bool
-/// true // If the download was added successfully
-/// false // If the download was not added successfully
+/// {
+///     "id": 1,
+///     "name": "Zelda",
+///     "game": "Zelda",
+///     "link": "magnet:?xt=urn:btih:...",
+///     "uploader": "Noidea",
+///     "path": "/home/user/Downloads/Zelda/Zelda",
+///     "status": "downloading",
+///     "priority": 1,
+///     "download_limit": 1048576,
+///     "upload_limit": null,
+///     "files": [0, 2],
+///     "info_hash": "8df6e26142615621983763b729f640372cf1fc34",
+///     "seeding_policy": "ratio:2",
+///     "extra_trackers": true,
+///     "error": null,
+///     "attempts": 0,
+///     "retry_at": null,
+///     "size": 2147483648,
+///     "bytes_done": 1073741824,
+///     "created_at": 1718000000,
+///     "completed_at": null,
+///     "file_names": ["Zelda/setup.exe", "Zelda/data.bin"],
+///     "uploaded_bytes": 0,
+///     "seeding_seconds": 0
+/// }
 /// ```
 ///
-pub async fn add_download(
-    name: &str,
-    game: &str,
-    link: &str,
-    uploader: &str,
-    path: &str,
-) -> Result<bool, RusqliteError> {
-
-    // Attempt to open a connection to the database
-    let conn = rusqlite::Connection::open("database.sqlite")?;
-
-    // Attempt to insert the download into the downloads table
-    conn.execute(
-        "INSERT INTO downloads (name, game, link, uploader, path) VALUES (?1, ?2, ?3, ?4, ?5)",
-        rusqlite::params![name, game, link, uploader, path],
-    )?;
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Download {
+    pub id: i64,
+    pub name: String,
+    pub game: String,
+    pub link: String,
+    pub uploader: String,
+    pub path: String,
+    pub status: DownloadStatus,
+    pub priority: i64,
+    pub download_limit: Option<u32>,
+    pub upload_limit: Option<u32>,
+    pub files: Option<Vec<usize>>,
+    pub info_hash: Option<String>,
+    pub seeding_policy: Option<String>,
+    pub extra_trackers: bool,
+    pub error: Option<String>,
+    pub attempts: u32,
+    pub retry_at: Option<i64>,
+    pub size: Option<u64>,
+    pub bytes_done: u64,
+    pub created_at: i64,
+    pub completed_at: Option<i64>,
+    pub file_names: Option<Vec<String>>,
+    pub uploaded_bytes: u64,
+    pub seeding_seconds: u64,
+}
+
+impl Download {
 
-    Ok(true)
+    /// Function to read a download from a row selected with DOWNLOAD_COLUMNS.
+    /// An unreadable list of files is an error, it must not turn a selection of files into the whole torrent.
+    fn from_row(row: &rusqlite::Row) -> Result<Self, RusqliteError> {
+        Ok(Self {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            game: row.get(2)?,
+            link: row.get(3)?,
+            uploader: row.get(4)?,
+            path: row.get(5)?,
+            status: row.get(6)?,
+            priority: row.get(7)?,
+            download_limit: row.get(8)?,
+            upload_limit: row.get(9)?,
+            files: json_column(row, 10)?,
+            info_hash: row.get(11)?,
+            seeding_policy: row.get(12)?,
+            extra_trackers: row.get(13)?,
+            error: row.get(14)?,
+            attempts: row.get(15)?,
+            retry_at: row.get(16)?,
+            size: row.get(17)?,
+            bytes_done: row.get(18)?,
+            created_at: row.get(19)?,
+            completed_at: row.get(20)?,
+            file_names: json_column(row, 21)?,
+            uploaded_bytes: row.get(22)?,
+            seeding_seconds: row.get(23)?,
+        })
+    }
 }
 
-/// Function to remove a download from the database.
-/// It will take the name of the torrent and remove it from the downloads table.
-/// It will return a Result<(), RusqliteError> to handle potential SQL execution errors.
+/// Function to read a nullable column holding a JSON value.
 ///
 /// # Arguments
-/// - name: &str (name of the torrent)
-///
-/// # Example
-/// ```rust
-/// remove_download("Zelda").await.unwrap();
-///
-/// # Returns
-/// ```bool
-/// true // If the download was removed successfully
-/// false // If the download was not removed successfully
-/// ```
+/// * `row` - The row read from the database.
+/// * `idx` - The index of the column.
 ///
+fn json_column<T: serde::de::DeserializeOwned>(row: &rusqlite::Row, idx: usize) -> Result<Option<T>, RusqliteError> {
+    match row.get::<_, Option<String>>(idx)? {
+        Some(value) => serde_json::from_str(&value)
+            .map(Some)
+            .map_err(|e| RusqliteError::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, Box::new(e))),
+        None => Ok(None),
+    }
+}
+
+// Columns of the downloads table, in the order expected by Download::from_row
+const DOWNLOAD_COLUMNS: &str = "id, name, game, link, uploader, path, status, priority, download_limit, upload_limit, files, info_hash, seeding_policy, extra_trackers, error, attempts, retry_at, size, bytes_done, created_at, completed_at, file_names, uploaded_bytes, seeding_seconds";
+
+/// Game installed in the library.
+/// The download id links the entry to the download it comes from, it can not be changed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LibraryEntry {
+    pub id: i64,
+    pub name: String,
+    pub game: String,
+    pub path: String,
+    pub executable: Option<String>,
+    #[serde(default)]
+    pub download_id: Option<i64>,
+}
+
+impl LibraryEntry {
+
+    /// Function to read a library entry from a row selected with LIBRARY_COLUMNS.
+    fn from_row(row: &rusqlite::Row) -> Result<Self, RusqliteError> {
+        Ok(Self {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            game: row.get(2)?,
+            path: row.get(3)?,
+            executable: row.get(4)?,
+            download_id: row.get(5)?,
+        })
+    }
+}
+
+// Columns of the library table, in the order expected by LibraryEntry::from_row
+const LIBRARY_COLUMNS: &str = "id, name, game, path, executable, download_id";
+
+impl Database {
+
+    /// Function to get the all the downloads from the database.
+    /// The downloads are ordered by priority, so the first queued one is the next to start.
+    /// Returns a Result<Vec<Download>, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Example
+    /// ```rust
+    /// let downloads = db.get_downloads().await.unwrap();
+    /// ```
+    ///
+    pub async fn get_downloads(&self) -> Result<Vec<Download>, RusqliteError> {
+        self.run(|conn| {
+
+            // Attempt to query the database for the downloads
+            let mut stmt = conn.prepare(&format!("SELECT {} FROM downloads ORDER BY priority, id", DOWNLOAD_COLUMNS))?;
+            let downloads = stmt
+                .query_map([], Download::from_row)?
+                .collect::<Result<Vec<Download>, RusqliteError>>()?;
+
+            Ok(downloads)
+        })
+        .await
+    }
+
+    /// Function to get a download from the database.
+    /// It will return a Result<Option<Download>, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - id: i64 (id of the download)
+    ///
+    /// # Example
+    /// ```rust
+    /// let download = db.get_download(1).await.unwrap();
+    /// ```
+    ///
+    pub async fn get_download(&self, id: i64) -> Result<Option<Download>, RusqliteError> {
+        self.run(move |conn| {
+
+            // Attempt to query the database for the download
+            conn.query_row(
+                &format!("SELECT {} FROM downloads WHERE id = ?1", DOWNLOAD_COLUMNS),
+                rusqlite::params![id],
+                Download::from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    /// Function to find a download by the info hash of its torrent.
+    /// Cancelled downloads are ignored, so a cancelled torrent can be downloaded again.
+    /// It will return a Result<Option<i64>, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - info_hash: &str (info hash of the torrent in hex)
+    ///
+    /// # Example
+    /// ```rust
+    /// let id = db.find_download_by_info_hash("8df6e26142615621983763b729f640372cf1fc34").await.unwrap();
+    /// ```
+    ///
+    pub async fn find_download_by_info_hash(&self, info_hash: &str) -> Result<Option<i64>, RusqliteError> {
+        let info_hash = info_hash.to_string();
+        self.run(move |conn| {
+
+            // Attempt to query the database for the download
+            conn.query_row(
+                "SELECT id FROM downloads WHERE info_hash = ?1 AND status != 'cancelled'",
+                rusqlite::params![info_hash],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+        .await
+    }
+
+    /// Function to add a download to the database.
+    /// It will take the name, game, link, uploader, directory, info hash and files of the torrent and add it to the downloads table.
+    /// The download is queued at the end of the queue.
+    /// A cancelled download of the same torrent gives up its info hash, any other download of it makes the insert fail.
+    /// It will return a Result<i64, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - name: &str (name of the torrent)
+    /// - game: &str (name of the game)
+    /// - link: &str (magnet link of the torrent)
+    /// - uploader: &str (name of the uploader)
+    /// - path: &str (directory of the download)
+    /// - info_hash: &str (info hash of the torrent in hex)
+    /// - files: Option<&[usize]> (indices of the files to download, None for the whole torrent)
+    ///
+    /// # Returns
+    /// ```i64
+    /// 1 // The id of the new download
+    /// ```
+    ///
+    pub async fn add_download(
+        &self,
+        name: &str,
+        game: &str,
+        link: &str,
+        uploader: &str,
+        path: &str,
+        info_hash: &str,
+        files: Option<&[usize]>,
+    ) -> Result<i64, RusqliteError> {
+        let params = [name, game, link, uploader, path, info_hash].map(str::to_string);
+        let files = files.map(|files| json!(files).to_string());
+        self.run(move |conn| {
+            let [name, game, link, uploader, path, info_hash] = params;
+
+            // The info hash is taken back and the download is inserted atomically
+            let tx = conn.transaction()?;
+
+            // Attempt to take the info hash back from the cancelled downloads
+            release_cancelled_info_hash(&tx, &info_hash)?;
+
+            // Attempt to insert the download into the downloads table
+            tx.execute(
+                "INSERT INTO downloads (name, game, link, uploader, path, info_hash, files, status, priority)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 'queued', (SELECT COALESCE(MAX(priority), 0) + 1 FROM downloads))",
+                rusqlite::params![name, game, link, uploader, path, info_hash, files],
+            )?;
+            let id = tx.last_insert_rowid();
+            tx.commit()?;
+
+            Ok(id)
+        })
+        .await
+    }
+
+    /// Function to update the status of a download.
+    /// It will be called when a download is paused, resumed or cancelled.
+    /// The completion time is set the first time the download is seeding or completed.
+    /// It will return a Result<bool, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - id: i64 (id of the download)
+    /// - status: DownloadStatus (new status of the download)
+    ///
+    /// # Example
+    /// ```rust
+    /// db.set_download_status(1, DownloadStatus::Paused).await.unwrap();
+    /// ```
+    ///
+    pub async fn set_download_status(&self, id: i64, status: DownloadStatus) -> Result<bool, RusqliteError> {
+        self.run(move |conn| {
+
+            // Attempt to update the status of the download
+            let updated = conn.execute(
+                "UPDATE downloads SET status = ?1,
+                    completed_at = CASE WHEN ?1 IN ('seeding', 'completed') THEN COALESCE(completed_at, strftime('%s', 'now')) ELSE completed_at END
+                    WHERE id = ?2",
+                rusqlite::params![status, id],
+            )?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    /// Function to update the priority of a download in the queue.
+    /// Lower priorities are started first.
+    /// It will return a Result<bool, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - id: i64 (id of the download)
+    /// - priority: i64 (new priority of the download)
+    ///
+    /// # Example
+    /// ```rust
+    /// db.set_download_priority(1, 3).await.unwrap();
+    /// ```
+    ///
+    pub async fn set_download_priority(&self, id: i64, priority: i64) -> Result<bool, RusqliteError> {
+        self.run(move |conn| {
+
+            // Attempt to update the priority of the download
+            let updated = conn.execute(
+                "UPDATE downloads SET priority = ?1 WHERE id = ?2",
+                rusqlite::params![priority, id],
+            )?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    /// Function to move a download to the top of the queue.
+    /// It gives the download a priority lower than every other download.
+    /// It will return a Result<bool, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - id: i64 (id of the download)
+    ///
+    /// # Example
+    /// ```rust
+    /// db.move_download_to_top(1).await.unwrap();
+    /// ```
+    ///
+    pub async fn move_download_to_top(&self, id: i64) -> Result<bool, RusqliteError> {
+        self.run(move |conn| {
+
+            // Attempt to update the priority of the download
+            let updated = conn.execute(
+                "UPDATE downloads SET priority = (SELECT COALESCE(MIN(priority), 0) - 1 FROM downloads) WHERE id = ?1",
+                rusqlite::params![id],
+            )?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    /// Function to store the info hash of a download.
+    /// It will be called once the torrent has been added to the session.
+    /// It will return a Result<bool, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - id: i64 (id of the download)
+    /// - info_hash: &str (info hash of the torrent in hex)
+    ///
+    /// # Example
+    /// ```rust
+    /// db.set_download_info_hash(1, "8df6e26142615621983763b729f640372cf1fc34").await.unwrap();
+    /// ```
+    ///
+    pub async fn set_download_info_hash(&self, id: i64, info_hash: &str) -> Result<bool, RusqliteError> {
+        let info_hash = info_hash.to_string();
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+
+            // Attempt to take the info hash back from the cancelled downloads
+            release_cancelled_info_hash(&tx, &info_hash)?;
+
+            // Attempt to update the info hash of the download
+            let updated = tx.execute(
+                "UPDATE downloads SET info_hash = ?1 WHERE id = ?2",
+                rusqlite::params![info_hash, id],
+            )?;
+            tx.commit()?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    /// Function to store the names of the files of a download.
+    /// It will be called once the torrent has been added to the session, the names are used to delete or move its files later.
+    /// It will return a Result<bool, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - id: i64 (id of the download)
+    /// - file_names: &[String] (paths of the files of the torrent, relative to the directory of the download)
+    ///
+    /// # Example
+    /// ```rust
+    /// db.set_download_file_names(1, &["Zelda/setup.exe".to_string()]).await.unwrap();
+    /// ```
+    ///
+    pub async fn set_download_file_names(&self, id: i64, file_names: &[String]) -> Result<bool, RusqliteError> {
+        let file_names = json!(file_names).to_string();
+        self.run(move |conn| {
+
+            // Attempt to update the file names of the download
+            let updated = conn.execute(
+                "UPDATE downloads SET file_names = ?1 WHERE id = ?2",
+                rusqlite::params![file_names, id],
+            )?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    /// Function to update the seeding policy of a download.
+    /// A NULL policy means that the download follows the global seeding policy.
+    /// It will return a Result<bool, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - id: i64 (id of the download)
+    /// - seeding_policy: Option<&str> (seeding policy of the download, e.g. "ratio:2")
+    ///
+    /// # Example
+    /// ```rust
+    /// db.set_download_seeding_policy(1, Some("ratio:2")).await.unwrap();
+    /// ```
+    ///
+    pub async fn set_download_seeding_policy(&self, id: i64, seeding_policy: Option<&str>) -> Result<bool, RusqliteError> {
+        let seeding_policy = seeding_policy.map(str::to_string);
+        self.run(move |conn| {
+
+            // Attempt to update the seeding policy of the download
+            let updated = conn.execute(
+                "UPDATE downloads SET seeding_policy = ?1 WHERE id = ?2",
+                rusqlite::params![seeding_policy, id],
+            )?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    /// Function to enable or disable the extra trackers of a download.
+    /// It will return a Result<bool, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - id: i64 (id of the download)
+    /// - extra_trackers: bool (whether the extra trackers are added to the magnet link)
+    ///
+    /// # Example
+    /// ```rust
+    /// db.set_download_extra_trackers(1, false).await.unwrap();
+    /// ```
+    ///
+    pub async fn set_download_extra_trackers(&self, id: i64, extra_trackers: bool) -> Result<bool, RusqliteError> {
+        self.run(move |conn| {
+
+            // Attempt to update the extra trackers flag of the download
+            let updated = conn.execute(
+                "UPDATE downloads SET extra_trackers = ?1 WHERE id = ?2",
+                rusqlite::params![extra_trackers, id],
+            )?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    /// Function to update the bandwidth limits of a download.
+    /// A NULL limit means that the download only follows the global limits.
+    /// It will return a Result<bool, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - id: i64 (id of the download)
+    /// - download_limit: Option<u32> (download limit in bytes per second)
+    /// - upload_limit: Option<u32> (upload limit in bytes per second)
+    ///
+    /// # Example
+    /// ```rust
+    /// db.set_download_limits(1, Some(1048576), None).await.unwrap();
+    /// ```
+    ///
+    pub async fn set_download_limits(
+        &self,
+        id: i64,
+        download_limit: Option<u32>,
+        upload_limit: Option<u32>,
+    ) -> Result<bool, RusqliteError> {
+        self.run(move |conn| {
+
+            // Attempt to update the limits of the download
+            let updated = conn.execute(
+                "UPDATE downloads SET download_limit = ?1, upload_limit = ?2 WHERE id = ?3",
+                rusqlite::params![download_limit, upload_limit, id],
+            )?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    /// Function to save the size and the downloaded bytes of a download.
+    /// It will be called periodically while the torrent runs.
+    /// It will return a Result<bool, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - id: i64 (id of the download)
+    /// - size: u64 (total size in bytes of the selected files)
+    /// - bytes_done: u64 (downloaded bytes)
+    ///
+    /// # Example
+    /// ```rust
+    /// db.set_download_progress(1, 2147483648, 1073741824).await.unwrap();
+    /// ```
+    ///
+    pub async fn set_download_progress(&self, id: i64, size: u64, bytes_done: u64) -> Result<bool, RusqliteError> {
+        self.run(move |conn| {
+
+            // Attempt to update the progress of the download
+            let updated = conn.execute(
+                "UPDATE downloads SET size = ?1, bytes_done = ?2 WHERE id = ?3",
+                rusqlite::params![size, bytes_done, id],
+            )?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    /// Function to save the uploaded bytes and the seeding time of a download.
+    /// It will be called periodically while the torrent seeds, the seeding policy is resumed from them after a restart.
+    /// It will return a Result<bool, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - id: i64 (id of the download)
+    /// - uploaded_bytes: u64 (uploaded bytes, including the previous runs)
+    /// - seeding_seconds: u64 (time in seconds the torrent has been seeding for, including the previous runs)
+    ///
+    /// # Example
+    /// ```rust
+    /// db.set_download_seeding(1, 1073741824, 3600).await.unwrap();
+    /// ```
+    ///
+    pub async fn set_download_seeding(&self, id: i64, uploaded_bytes: u64, seeding_seconds: u64) -> Result<bool, RusqliteError> {
+        self.run(move |conn| {
+
+            // Attempt to update the seeding of the download
+            let updated = conn.execute(
+                "UPDATE downloads SET uploaded_bytes = ?1, seeding_seconds = ?2 WHERE id = ?3",
+                rusqlite::params![uploaded_bytes, seeding_seconds, id],
+            )?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    /// Function to record the error of a download that failed to start.
+    /// The status of the download is set to error and its number of attempts is increased.
+    /// Only the downloads that are meant to run are marked, a paused, cancelled or completed download keeps its status
+    /// so that it is never retried automatically.
+    /// It will return a Result<bool, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - id: i64 (id of the download)
+    /// - error: &str (message of the error)
+    /// - retry_at: Option<i64> (unix time of the next automatic retry, None to stop retrying)
+    ///
+    /// # Example
+    /// ```rust
+    /// db.set_download_error(1, "not enough disk space", Some(1718000030)).await.unwrap();
+    /// ```
+    ///
+    pub async fn set_download_error(&self, id: i64, error: &str, retry_at: Option<i64>) -> Result<bool, RusqliteError> {
+        let error = error.to_string();
+        self.run(move |conn| {
+
+            // Attempt to record the error of the download
+            let updated = conn.execute(
+                "UPDATE downloads SET status = 'error', error = ?1, attempts = attempts + 1, retry_at = ?2
+                    WHERE id = ?3 AND status IN ('queued', 'downloading', 'seeding')",
+                rusqlite::params![error, retry_at, id],
+            )?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    /// Function to clear the error and the attempts of a download.
+    /// It will be called when a download succeeds or when the user retries it.
+    /// It will return a Result<bool, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - id: i64 (id of the download)
+    ///
+    /// # Example
+    /// ```rust
+    /// db.clear_download_error(1).await.unwrap();
+    /// ```
+    ///
+    pub async fn clear_download_error(&self, id: i64) -> Result<bool, RusqliteError> {
+        self.run(move |conn| {
+
+            // Attempt to clear the error of the download
+            let updated = conn.execute(
+                "UPDATE downloads SET error = NULL, attempts = 0, retry_at = NULL WHERE id = ?1",
+                rusqlite::params![id],
+            )?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    /// Function to put back in the queue the failed downloads whose retry time has come.
+    /// The error and the attempts are kept, so the next failure waits longer.
+    /// It will return a Result<usize, RusqliteError> with the number of requeued downloads.
+    ///
+    /// # Arguments
+    /// - now: i64 (current unix time)
+    ///
+    /// # Example
+    /// ```rust
+    /// let requeued = db.requeue_failed_downloads(chrono::Utc::now().timestamp()).await.unwrap();
+    /// ```
+    ///
+    pub async fn requeue_failed_downloads(&self, now: i64) -> Result<usize, RusqliteError> {
+        self.run(move |conn| {
+
+            // Attempt to requeue the downloads
+            conn.execute(
+                "UPDATE downloads SET status = 'queued', retry_at = NULL WHERE status = 'error' AND retry_at <= ?1",
+                rusqlite::params![now],
+            )
+        })
+        .await
+    }
+
+    /// Function to update the directory of a download.
+    /// It will be called when the files of a completed download are moved to the library.
+    /// It will return a Result<bool, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - id: i64 (id of the download)
+    /// - path: &str (new directory of the download)
+    ///
+    /// # Example
+    /// ```rust
+    /// db.set_download_path(1, "/home/user/Games/Zelda").await.unwrap();
+    /// ```
+    ///
+    pub async fn set_download_path(&self, id: i64, path: &str) -> Result<bool, RusqliteError> {
+        let path = path.to_string();
+        self.run(move |conn| {
+
+            // Attempt to update the directory of the download
+            let updated = conn.execute(
+                "UPDATE downloads SET path = ?1 WHERE id = ?2",
+                rusqlite::params![path, id],
+            )?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    /// Function to remove a download from the database.
+    /// It will take the id of the download and remove it from the downloads table.
+    /// It will return a Result<bool, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - id: i64 (id of the download)
+    ///
+    /// # Example
+    /// ```rust
+    /// db.remove_download(1).await.unwrap();
+    /// ```
+    ///
+    /// # Returns
+    /// ```bool
+    /// true // If the download was removed successfully
+    /// false // If the download was not removed successfully
+    /// ```
+    ///
+    pub async fn remove_download(&self, id: i64) -> Result<bool, RusqliteError> {
+        self.run(move |conn| {
+
+            // Attempt to remove the download from the downloads table
+            let removed = conn.execute(
+                "DELETE FROM downloads WHERE id = ?1",
+                rusqlite::params![id],
+            )?;
+
+            Ok(removed > 0)
+        })
+        .await
+    }
 
-pub async fn remove_download(name: &str) -> Result<bool, RusqliteError> {
+    /// Function to add a game to the library.
+    /// It will be called when a download completes, the executable is not known yet.
+    /// A download adds a single entry, adding it again returns the id of its existing entry.
+    /// It will return a Result<i64, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - download_id: Option<i64> (id of the download the game comes from, None for a game added by hand)
+    /// - name: &str (name of the download)
+    /// - game: &str (name of the game)
+    /// - path: &str (path to the game)
+    ///
+    /// # Returns
+    /// ```i64
+    /// 1 // The id of the library entry
+    /// ```
+    ///
+    pub async fn add_library_entry(&self, download_id: Option<i64>, name: &str, game: &str, path: &str) -> Result<i64, RusqliteError> {
+        let params = [name, game, path].map(str::to_string);
+        self.run(move |conn| {
+            let [name, game, path] = params;
 
-    // Attempt to open a connection to the database
-    let conn = rusqlite::Connection::open("database.sqlite")?;
+            // Attempt to insert the game into the library table, unless its download already added it
+            let inserted = conn.execute(
+                "INSERT INTO library (download_id, name, game, path) VALUES (?1, ?2, ?3, ?4)
+                    ON CONFLICT (download_id) DO NOTHING",
+                rusqlite::params![download_id, name, game, path],
+            )?;
+            if inserted > 0 {
+                return Ok(conn.last_insert_rowid());
+            }
 
-    // Attempt to remove the download from the downloads table
+            conn.query_row(
+                "SELECT id FROM library WHERE download_id = ?1",
+                rusqlite::params![download_id],
+                |row| row.get(0),
+            )
+        })
+        .await
+    }
+
+    /// Function to find the library entry added by a download.
+    /// It will return a Result<Option<i64>, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - download_id: i64 (id of the download)
+    ///
+    /// # Example
+    /// ```rust
+    /// let id = db.find_library_entry_by_download(1).await.unwrap();
+    /// ```
+    ///
+    pub async fn find_library_entry_by_download(&self, download_id: i64) -> Result<Option<i64>, RusqliteError> {
+        self.run(move |conn| {
+
+            // Attempt to query the database for the library entry
+            conn.query_row(
+                "SELECT id FROM library WHERE download_id = ?1",
+                rusqlite::params![download_id],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+        .await
+    }
+
+    /// Function to update the path of a game in the library.
+    /// It will return a Result<bool, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - id: i64 (id of the library entry)
+    /// - path: &str (new path to the game)
+    ///
+    /// # Example
+    /// ```rust
+    /// db.set_library_path(1, "/home/user/Games/Zelda").await.unwrap();
+    /// ```
+    ///
+    pub async fn set_library_path(&self, id: i64, path: &str) -> Result<bool, RusqliteError> {
+        let path = path.to_string();
+        self.run(move |conn| {
+
+            // Attempt to update the path of the game
+            let updated = conn.execute(
+                "UPDATE library SET path = ?1 WHERE id = ?2",
+                rusqlite::params![path, id],
+            )?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    /// Function to get all the games of the library, ordered by game.
+    /// It will return a Result<Vec<LibraryEntry>, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Example
+    /// ```rust
+    /// let library = db.get_library().await.unwrap();
+    /// ```
+    ///
+    pub async fn get_library(&self) -> Result<Vec<LibraryEntry>, RusqliteError> {
+        self.run(|conn| {
+
+            // Attempt to query the database for the library
+            let mut stmt = conn.prepare(&format!("SELECT {} FROM library ORDER BY game, id", LIBRARY_COLUMNS))?;
+            let library = stmt
+                .query_map([], LibraryEntry::from_row)?
+                .collect::<Result<Vec<LibraryEntry>, RusqliteError>>()?;
+
+            Ok(library)
+        })
+        .await
+    }
+
+    /// Function to get a game of the library.
+    /// It will return a Result<Option<LibraryEntry>, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - id: i64 (id of the library entry)
+    ///
+    /// # Example
+    /// ```rust
+    /// let entry = db.get_library_entry(1).await.unwrap();
+    /// ```
+    ///
+    pub async fn get_library_entry(&self, id: i64) -> Result<Option<LibraryEntry>, RusqliteError> {
+        self.run(move |conn| {
+
+            // Attempt to query the database for the game
+            conn.query_row(
+                &format!("SELECT {} FROM library WHERE id = ?1", LIBRARY_COLUMNS),
+                rusqlite::params![id],
+                LibraryEntry::from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    /// Function to update a game of the library.
+    /// The entry is found by its id, its name, game, path and executable are replaced.
+    /// It will return a Result<bool, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - entry: &LibraryEntry (updated library entry)
+    ///
+    /// # Example
+    /// ```rust
+    /// let mut entry = db.get_library_entry(1).await.unwrap().unwrap();
+    /// entry.executable = Some("/home/user/Games/Zelda/zelda.exe".to_string());
+    /// db.update_library_entry(&entry).await.unwrap();
+    /// ```
+    ///
+    pub async fn update_library_entry(&self, entry: &LibraryEntry) -> Result<bool, RusqliteError> {
+        let entry = entry.clone();
+        self.run(move |conn| {
+
+            // Attempt to update the game
+            let updated = conn.execute(
+                "UPDATE library SET name = ?1, game = ?2, path = ?3, executable = ?4 WHERE id = ?5",
+                rusqlite::params![entry.name, entry.game, entry.path, entry.executable, entry.id],
+            )?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    /// Function to remove a game from the library.
+    /// The files of the game are not deleted.
+    /// It will return a Result<bool, RusqliteError> to handle potential SQL execution errors.
+    ///
+    /// # Arguments
+    /// - id: i64 (id of the library entry)
+    ///
+    /// # Example
+    /// ```rust
+    /// db.remove_library_entry(1).await.unwrap();
+    /// ```
+    ///
+    pub async fn remove_library_entry(&self, id: i64) -> Result<bool, RusqliteError> {
+        self.run(move |conn| {
+
+            // Attempt to remove the game from the library table
+            let removed = conn.execute(
+                "DELETE FROM library WHERE id = ?1",
+                rusqlite::params![id],
+            )?;
+
+            Ok(removed > 0)
+        })
+        .await
+    }
+}
+
+/// Function to clear the info hash of the cancelled downloads of a torrent.
+/// The info hash is unique, so a cancelled download must give it up before the torrent is downloaded again.
+fn release_cancelled_info_hash(conn: &rusqlite::Connection, info_hash: &str) -> Result<(), RusqliteError> {
     conn.execute(
-        "DELETE FROM downloads WHERE name = ?1",
-        rusqlite::params![name],
+        "UPDATE downloads SET info_hash = NULL WHERE info_hash = ?1 AND status = 'cancelled'",
+        rusqlite::params![info_hash],
     )?;
 
-    Ok(true)
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests{
 
     use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    // Counter used to give a different info hash to each download of the tests
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    /// Function to generate an info hash that is not in the database yet.
+    fn info_hash() -> String {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        format!("{:032x}{:08x}", nanos, COUNTER.fetch_add(1, Ordering::SeqCst))
+    }
+
+    /// Function to get the path of the database of the tests.
+    /// It is a file of the temporary directory, so the tests never touch the database.sqlite of the application.
+    fn database_path() -> String {
+        std::env::temp_dir()
+            .join(format!("gameio-database-{}.sqlite", std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Function to open the database of the tests, each test opens its own connection to the same file.
+    fn database() -> Database {
+        Database::open(&database_path()).unwrap()
+    }
 
     #[tokio::test]
-    async fn create_database_sqlite_test() {
-        create_database_sqlite();
-        assert!(std::path::Path::new("database.sqlite").exists());
+    async fn open_test() {
+        let db = database();
+        assert!(std::path::Path::new(&database_path()).exists());
+
+        let journal_mode = db.run(|conn| conn.query_row("PRAGMA journal_mode", [], |row| row.get::<_, String>(0))).await.unwrap();
+        assert_eq!(journal_mode, "wal");
     }
 
     #[tokio::test]
     async fn add_download_test() {
-        create_database_sqlite();
-        let result = add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path").await.unwrap();
-        assert_eq!(result, true);
+        let db = database();
+        let result = db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &info_hash(), None).await.unwrap();
+        assert!(result > 0);
     }
 
     #[tokio::test]
     async fn get_downloads_test() {
-        create_database_sqlite();
-        let downloads: Vec<Value> = get_downloads().await.unwrap();
+        let db = database();
+        let downloads: Vec<Download> = db.get_downloads().await.unwrap();
         print!("{:?}", downloads);
         assert!(!downloads.is_empty());
     }
 
+    #[tokio::test]
+    async fn get_download_test() {
+        let db = database();
+        let hash = info_hash();
+        let id = db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &hash, Some(&[0, 2])).await.unwrap();
+
+        let download = db.get_download(id).await.unwrap().unwrap();
+        assert_eq!(download.name, "Zelda");
+        assert_eq!(download.status, DownloadStatus::Queued);
+        assert_eq!(download.info_hash, Some(hash));
+        assert_eq!(download.files, Some(vec![0, 2]));
+        assert_eq!(download.download_limit, None);
+        assert_eq!(download.completed_at, None);
+    }
+
+    #[tokio::test]
+    async fn get_download_invalid_files_test() {
+        // The unreadable row would make get_downloads fail in the other tests, it gets its own file
+        let path = std::env::temp_dir().join(format!("gameio-database-invalid-{}.sqlite", std::process::id()));
+        let db = Database::open(path.to_str().unwrap()).unwrap();
+        let id = db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &info_hash(), Some(&[0, 2])).await.unwrap();
+        db.run(move |conn| conn.execute("UPDATE downloads SET files = 'not json' WHERE id = ?1", [id])).await.unwrap();
+
+        // The selection of files is not read as the whole torrent
+        assert!(db.get_download(id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn set_download_status_test() {
+        let db = database();
+        let id = db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &info_hash(), None).await.unwrap();
+        let result = db.set_download_status(id, DownloadStatus::Paused).await.unwrap();
+        assert_eq!(result, true);
+    }
+
+    #[tokio::test]
+    async fn move_download_to_top_test() {
+        let db = database();
+        db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &info_hash(), None).await.unwrap();
+        let id = db.add_download("Mario", "Mario", "magnet:?xt=urn:btih:...", "Noidea", "path", &info_hash(), None).await.unwrap();
+        db.move_download_to_top(id).await.unwrap();
+        let downloads = db.get_downloads().await.unwrap();
+        assert_eq!(downloads[0].id, id);
+    }
+
+    #[tokio::test]
+    async fn set_download_info_hash_test() {
+        let db = database();
+        let id = db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &info_hash(), None).await.unwrap();
+        let result = db.set_download_info_hash(id, &info_hash()).await.unwrap();
+        assert_eq!(result, true);
+    }
+
+    #[tokio::test]
+    async fn find_download_by_info_hash_test() {
+        let db = database();
+        let id = db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &info_hash(), None).await.unwrap();
+        let hash = info_hash();
+        db.set_download_info_hash(id, &hash).await.unwrap();
+        let result = db.find_download_by_info_hash(&hash).await.unwrap();
+        assert_eq!(result, Some(id));
+    }
+
+    #[tokio::test]
+    async fn set_download_seeding_policy_test() {
+        let db = database();
+        let id = db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &info_hash(), None).await.unwrap();
+        let result = db.set_download_seeding_policy(id, Some("ratio:2")).await.unwrap();
+        assert_eq!(result, true);
+    }
+
+    #[tokio::test]
+    async fn set_download_extra_trackers_test() {
+        let db = database();
+        let id = db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &info_hash(), None).await.unwrap();
+        let result = db.set_download_extra_trackers(id, false).await.unwrap();
+        assert_eq!(result, true);
+    }
+
+    #[tokio::test]
+    async fn set_download_limits_test() {
+        let db = database();
+        let id = db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &info_hash(), None).await.unwrap();
+        let result = db.set_download_limits(id, Some(1048576), None).await.unwrap();
+        assert_eq!(result, true);
+    }
+
+    #[tokio::test]
+    async fn unique_info_hash_test() {
+        let db = database();
+        let hash = info_hash();
+        let id = db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &hash, None).await.unwrap();
+        assert!(db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &hash, None).await.is_err());
+
+        // A cancelled download gives up its info hash
+        db.set_download_status(id, DownloadStatus::Cancelled).await.unwrap();
+        let result = db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &hash, None).await.unwrap();
+        assert!(result > id);
+    }
+
+    #[tokio::test]
+    async fn set_download_progress_test() {
+        let db = database();
+        let id = db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &info_hash(), None).await.unwrap();
+        let result = db.set_download_progress(id, 2048, 1024).await.unwrap();
+        assert_eq!(result, true);
+    }
+
+    #[tokio::test]
+    async fn set_download_seeding_test() {
+        let db = database();
+        let id = db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &info_hash(), None).await.unwrap();
+        let result = db.set_download_seeding(id, 4096, 60).await.unwrap();
+        assert_eq!(result, true);
+
+        let download = db.get_download(id).await.unwrap().unwrap();
+        assert_eq!((download.uploaded_bytes, download.seeding_seconds), (4096, 60));
+    }
+
+    #[tokio::test]
+    async fn set_download_file_names_test() {
+        let db = database();
+        let id = db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &info_hash(), None).await.unwrap();
+        let file_names = vec!["Zelda/setup.exe".to_string(), "Zelda/data.bin".to_string()];
+        let result = db.set_download_file_names(id, &file_names).await.unwrap();
+        assert_eq!(result, true);
+
+        let download = db.get_download(id).await.unwrap().unwrap();
+        assert_eq!(download.file_names, Some(file_names));
+    }
+
+    #[tokio::test]
+    async fn set_download_error_test() {
+        let db = database();
+        let id = db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &info_hash(), None).await.unwrap();
+        let result = db.set_download_error(id, "not enough disk space", Some(0)).await.unwrap();
+        assert_eq!(result, true);
+
+        let requeued = db.requeue_failed_downloads(1).await.unwrap();
+        assert!(requeued > 0);
+
+        let result = db.clear_download_error(id).await.unwrap();
+        assert_eq!(result, true);
+
+        db.set_download_status(id, DownloadStatus::Paused).await.unwrap();
+        let result = db.set_download_error(id, "not enough disk space", Some(0)).await.unwrap();
+        assert_eq!(result, false);
+    }
+
+    #[tokio::test]
+    async fn set_download_path_test() {
+        let db = database();
+        let id = db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &info_hash(), None).await.unwrap();
+        let result = db.set_download_path(id, "library").await.unwrap();
+        assert_eq!(result, true);
+    }
+
+    #[tokio::test]
+    async fn add_library_entry_test() {
+        let db = database();
+        let id = db.add_library_entry(None, "Zelda", "Zelda", "path").await.unwrap();
+        assert!(id > 0);
+
+        let result = db.set_library_path(id, "library").await.unwrap();
+        assert_eq!(result, true);
+    }
+
+    #[tokio::test]
+    async fn add_library_entry_once_test() {
+        let db = database();
+        let download_id = db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &info_hash(), None).await.unwrap();
+        let id = db.add_library_entry(Some(download_id), "Zelda", "Zelda", "path").await.unwrap();
+        assert_eq!(db.add_library_entry(Some(download_id), "Zelda", "Zelda", "path").await.unwrap(), id);
+
+        let entry = db.get_library_entry(id).await.unwrap().unwrap();
+        assert_eq!(entry.download_id, Some(download_id));
+        assert_eq!(db.find_library_entry_by_download(download_id).await.unwrap(), Some(id));
+    }
+
+    #[tokio::test]
+    async fn get_library_test() {
+        let db = database();
+        let id = db.add_library_entry(None, "Zelda", "Zelda", "path").await.unwrap();
+        let library = db.get_library().await.unwrap();
+        assert!(library.iter().any(|entry| entry.id == id));
+    }
+
+    #[tokio::test]
+    async fn update_library_entry_test() {
+        let db = database();
+        let id = db.add_library_entry(None, "Zelda", "Zelda", "path").await.unwrap();
+        let mut entry = db.get_library_entry(id).await.unwrap().unwrap();
+        assert_eq!(entry.executable, None);
+
+        entry.executable = Some("path/zelda.exe".to_string());
+        let result = db.update_library_entry(&entry).await.unwrap();
+        assert_eq!(result, true);
+        assert_eq!(db.get_library_entry(id).await.unwrap(), Some(entry));
+    }
+
+    #[tokio::test]
+    async fn remove_library_entry_test() {
+        let db = database();
+        let id = db.add_library_entry(None, "Zelda", "Zelda", "path").await.unwrap();
+        let result = db.remove_library_entry(id).await.unwrap();
+        assert_eq!(result, true);
+        assert_eq!(db.get_library_entry(id).await.unwrap(), None);
+    }
+
     #[tokio::test]
     async fn remove_download_test() {
-        create_database_sqlite();
-        add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path").await.unwrap();
-        let result = remove_download("Zelda").await.unwrap();
+        let db = database();
+        let id = db.add_download("Zelda", "Zelda", "magnet:?xt=urn:btih:...", "Noidea", "path", &info_hash(), None).await.unwrap();
+        let result = db.remove_download(id).await.unwrap();
         assert_eq!(result, true);
+
+        let result = db.remove_download(id).await.unwrap();
+        assert_eq!(result, false);
     }
     
 }
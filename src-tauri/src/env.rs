@@ -1,8 +1,120 @@
 use std::env::VarError;
-use std::fs::{self, OpenOptions};
-use std::io::{self, Write}; // Keep this import as it includes Write
+use std::fs;
+use std::io;
+
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::RwLock;
+
+use crate::magnet;
+use crate::paths;
+use crate::scheduler::{self, ScheduleWindow};
+use crate::torrent::{NetworkOptions, SeedingPolicy};
+
+// Variables of the .env file, loaded once and shared by the whole application
+static ENV: Lazy<EnvFile> = Lazy::new(|| EnvFile::open(env_file_path()));
+
+/// Function to get the path of the environment file of the application.
+#[cfg(not(test))]
+fn env_file_path() -> PathBuf {
+    PathBuf::from(".env")
+}
+
+/// Function to get the path of the environment file used by the tests.
+/// It is a copy of the .env file in the temporary directory, so the tests never overwrite the settings of the user.
+#[cfg(test)]
+fn env_file_path() -> PathBuf {
+    let path = std::env::temp_dir().join(format!("gameio-env-{}.env", std::process::id()));
+    let _ = fs::copy(".env", &path);
+    path
+}
+
+/// Store of the variables of an environment file.
+/// The variables are kept in memory behind a lock and the file is rewritten every time they change.
+/// The environment of the process is only read, as a fallback, because changing it while other threads run is unsound.
+pub struct EnvFile {
+    path: PathBuf,
+    vars: RwLock<BTreeMap<String, String>>,
+}
+
+impl EnvFile {
+
+    /// Function to load the variables of an environment file.
+    /// A missing or unreadable file is read as an empty one.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the environment file.
+    ///
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let vars = fs::read_to_string(&path)
+            .map(|contents| parse_env(&contents))
+            .unwrap_or_default();
+
+        Self { path, vars: RwLock::new(vars) }
+    }
+
+    /// Function to get the path of the environment file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Function to get a variable, from the file or else from the environment of the process.
+    ///
+    /// # Arguments
+    /// * `key` - The name of the variable.
+    ///
+    pub fn get(&self, key: &str) -> Result<String, VarError> {
+        let vars = self.vars.read().unwrap_or_else(|e| e.into_inner());
+        match vars.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => std::env::var(key),
+        }
+    }
+
+    /// Function to set some variables and write them to the file.
+    /// The variables are only updated in memory once the file is written.
+    ///
+    /// # Arguments
+    /// * `updates` - The names and the new values of the variables.
+    ///
+    pub fn set(&self, updates: &[(&str, &str)]) -> Result<(), io::Error> {
+        let mut vars = self.vars.write().unwrap_or_else(|e| e.into_inner());
+
+        let mut updated = vars.clone();
+        for (key, value) in updates {
+            updated.insert(key.to_string(), value.to_string());
+        }
 
-use std::collections::HashMap;
+        let contents: String = updated.iter().map(|(key, value)| format!("{}={}\n", key, value)).collect();
+        fs::write(&self.path, contents)?;
+
+        *vars = updated;
+        Ok(())
+    }
+}
+
+/// Function to parse the KEY=VALUE lines of an environment file.
+/// Empty lines and comments are skipped, the values can be quoted.
+fn parse_env(contents: &str) -> BTreeMap<String, String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| {
+            let key = key.trim().trim_start_matches("export ").trim();
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            (key.to_string(), value.to_string())
+        })
+        .collect()
+}
 
 /// Function to make a GET request to a URL and return the JSON response.
 /// It will be used to make requests to the RAWG API.
@@ -19,9 +131,7 @@ use std::collections::HashMap;
 /// Function to retrieve the ID_CLIENT from the environment file.
 /// It will be used to make requests to the IGDB API.
 pub async fn get_id_client() -> Result<String, VarError> {
-    dotenv::dotenv().ok();
-
-    let id_client = std::env::var("ID_CLIENT")?;
+    let id_client = ENV.get("ID_CLIENT")?;
 
     Ok(id_client)
 }
@@ -29,9 +139,7 @@ pub async fn get_id_client() -> Result<String, VarError> {
 /// Function to retrieve the SECRET from the environment file.
 /// It will be used to make requests to the IGDB API.
 pub async fn get_secret() -> Result<String, VarError> {
-    dotenv::dotenv().ok();
-
-    let secret = std::env::var("SECRET")?;
+    let secret = ENV.get("SECRET")?;
 
     Ok(secret)
 }
@@ -39,9 +147,7 @@ pub async fn get_secret() -> Result<String, VarError> {
 /// Function to retrieve the DOWNLOAD_PATH from the environment file.
 /// It will be used to know where to download the torrents.
 pub async fn get_download_path() -> Result<String, VarError> {
-    dotenv::dotenv().ok();
-
-    let download_path = std::env::var("DOWNLOAD_PATH")?;
+    let download_path = ENV.get("DOWNLOAD_PATH")?;
 
     Ok(download_path)
 }
@@ -49,29 +155,174 @@ pub async fn get_download_path() -> Result<String, VarError> {
 /// Function to set the DOWNLOAD_PATH in the environment file.
 /// It will be used to update the download path in the application.
 pub async fn set_download_path(download_path: &str) -> Result<(), io::Error> {
-    dotenv::dotenv().ok();
+    set_var("DOWNLOAD_PATH", download_path)
+}
 
-    let mut env_vars = HashMap::new();
+/// Function to retrieve the PATH_TEMPLATE from the environment file.
+/// It decides the directory of each download, e.g. {download_path}/{game}/{torrent_name}.
+pub async fn get_path_template() -> String {
+    get_var_or("PATH_TEMPLATE", paths::DEFAULT_PATH_TEMPLATE.to_string())
+}
 
-    // Read the current .env file
-    if let Ok(contents) = fs::read_to_string(".env") {
-        for line in contents.lines() {
-            if let Some((key, value)) = line.split_once('=') {
-                env_vars.insert(key.to_string(), value.to_string());
-            }
-        }
+/// Function to set the PATH_TEMPLATE in the environment file.
+/// It will be used to update the directory of the next downloads from the settings page.
+pub async fn set_path_template(path_template: &str) -> Result<(), io::Error> {
+    set_var("PATH_TEMPLATE", path_template)
+}
+
+/// Function to resolve the directory of a download from the DOWNLOAD_PATH and the PATH_TEMPLATE.
+///
+/// # Arguments
+/// * `game` - The name of the game of the download.
+/// * `torrent_name` - The name of the torrent.
+///
+pub async fn get_game_download_path(game: &str, torrent_name: &str) -> Result<String, VarError> {
+    let download_path = get_download_path().await?;
+    let template = get_path_template().await;
+
+    Ok(paths::resolve_download_path(&template, &download_path, game, torrent_name))
+}
+
+/// Function to retrieve the LIBRARY_PATH from the environment file.
+/// The completed downloads are moved there once they stop seeding, empty to keep them in the DOWNLOAD_PATH.
+pub async fn get_library_path() -> String {
+    get_var_or("LIBRARY_PATH", String::new())
+}
+
+/// Function to set the LIBRARY_PATH in the environment file.
+/// It will be used to update the library directory from the settings page.
+pub async fn set_library_path(library_path: &str) -> Result<(), io::Error> {
+    set_var("LIBRARY_PATH", library_path)
+}
+
+/// Function to retrieve the DOWNLOAD_LIMIT from the environment file.
+/// It is the global download limit in bytes per second, 0 means unlimited.
+pub async fn get_download_limit() -> u32 {
+    get_var_or("DOWNLOAD_LIMIT", 0)
+}
+
+/// Function to retrieve the UPLOAD_LIMIT from the environment file.
+/// It is the global upload limit in bytes per second, 0 means unlimited.
+pub async fn get_upload_limit() -> u32 {
+    get_var_or("UPLOAD_LIMIT", 0)
+}
+
+/// Function to set the DOWNLOAD_LIMIT and UPLOAD_LIMIT in the environment file.
+/// It will be used to update the global bandwidth limits in the application.
+pub async fn set_bandwidth_limits(download_limit: u32, upload_limit: u32) -> Result<(), io::Error> {
+    set_vars(&[
+        ("DOWNLOAD_LIMIT", &download_limit.to_string()),
+        ("UPLOAD_LIMIT", &upload_limit.to_string()),
+    ])
+}
+
+/// Function to retrieve the MAX_ACTIVE_DOWNLOADS from the environment file.
+/// It is the number of downloads that can run at the same time, the others wait in the queue.
+pub async fn get_max_active_downloads() -> usize {
+    get_var_or("MAX_ACTIVE_DOWNLOADS", 3)
+}
+
+/// Function to set the MAX_ACTIVE_DOWNLOADS in the environment file.
+/// It will be used to update the size of the download queue in the application.
+pub async fn set_max_active_downloads(max_active_downloads: usize) -> Result<(), io::Error> {
+    set_var("MAX_ACTIVE_DOWNLOADS", &max_active_downloads.to_string())
+}
+
+/// Function to retrieve the SEEDING_POLICY from the environment file.
+/// It decides when completed torrents stop seeding, unless a download overrides it.
+pub async fn get_seeding_policy() -> SeedingPolicy {
+    get_var_or("SEEDING_POLICY", SeedingPolicy::default())
+}
+
+/// Function to set the SEEDING_POLICY in the environment file.
+/// It will be used to update the global seeding policy in the application.
+pub async fn set_seeding_policy(seeding_policy: SeedingPolicy) -> Result<(), io::Error> {
+    set_var("SEEDING_POLICY", &seeding_policy.to_string())
+}
+
+/// Function to retrieve the network options of the torrent session from the environment file.
+/// They are read from LISTEN_PORT_START, LISTEN_PORT_END and ENABLE_DHT.
+pub async fn get_network_options() -> NetworkOptions {
+    let default = NetworkOptions::default();
+
+    NetworkOptions {
+        listen_port_start: get_var_or("LISTEN_PORT_START", default.listen_port_start),
+        listen_port_end: get_var_or("LISTEN_PORT_END", default.listen_port_end),
+        dht: get_var_or("ENABLE_DHT", default.dht),
     }
+}
 
-    // Update the DOWNLOAD_PATH variable
-    env_vars.insert("DOWNLOAD_PATH".to_string(), download_path.to_string());
+/// Function to set the network options of the torrent session in the environment file.
+/// They will be used the next time the session is created.
+pub async fn set_network_options(options: NetworkOptions) -> Result<(), io::Error> {
+    set_vars(&[
+        ("LISTEN_PORT_START", &options.listen_port_start.to_string()),
+        ("LISTEN_PORT_END", &options.listen_port_end.to_string()),
+        ("ENABLE_DHT", &options.dht.to_string()),
+    ])
+}
 
-    // Write the updated contents back to the .env file
-    let mut file = OpenOptions::new().write(true).truncate(true).open(".env")?;
-    for (key, value) in env_vars {
-        writeln!(file, "{}={}", key, value)?;
+/// Function to retrieve the extra trackers added to the magnet links.
+/// They are read from the EXTRA_TRACKERS variable (comma separated)
+/// and from the file at EXTRA_TRACKERS_FILE (one tracker per line).
+pub async fn get_extra_trackers() -> Vec<String> {
+    let mut trackers: String = get_var_or("EXTRA_TRACKERS", String::new());
+
+    // Append the content of the tracker list file, if any
+    let file: String = get_var_or("EXTRA_TRACKERS_FILE", String::new());
+    if !file.is_empty() {
+        if let Ok(contents) = fs::read_to_string(&file) {
+            trackers.push('\n');
+            trackers.push_str(&contents);
+        }
     }
 
-    Ok(())
+    magnet::parse_tracker_list(&trackers)
+}
+
+/// Function to retrieve the EXTRA_TRACKERS_FILE from the environment file.
+/// It is the path of a local tracker list, empty if there is none.
+pub async fn get_extra_trackers_file() -> String {
+    get_var_or("EXTRA_TRACKERS_FILE", String::new())
+}
+
+/// Function to set the EXTRA_TRACKERS and EXTRA_TRACKERS_FILE in the environment file.
+/// It will be used to update the extra trackers from the settings page.
+pub async fn set_extra_trackers(trackers: &[String], file: &str) -> Result<(), io::Error> {
+    set_vars(&[("EXTRA_TRACKERS", &trackers.join(",")), ("EXTRA_TRACKERS_FILE", file)])
+}
+
+/// Function to retrieve the SCHEDULE from the environment file.
+/// It is the list of weekly windows in which the downloads can run, empty to always run them.
+pub async fn get_schedule() -> Vec<ScheduleWindow> {
+    let schedule: String = get_var_or("SCHEDULE", String::new());
+
+    scheduler::parse_schedule(&schedule).unwrap_or_default()
+}
+
+/// Function to set the SCHEDULE in the environment file.
+/// It will be used to update the download windows from the settings page.
+pub async fn set_schedule(windows: &[ScheduleWindow]) -> Result<(), io::Error> {
+    set_var("SCHEDULE", &scheduler::format_schedule(windows))
+}
+
+/// Function to retrieve a variable from the environment file and parse it.
+/// It will return the default value if the variable is missing or invalid.
+fn get_var_or<T: FromStr>(key: &str, default: T) -> T {
+    ENV.get(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Function to set a variable in the environment file.
+fn set_var(key: &str, value: &str) -> Result<(), io::Error> {
+    set_vars(&[(key, value)])
+}
+
+/// Function to set several variables in the environment file with a single write.
+fn set_vars(updates: &[(&str, &str)]) -> Result<(), io::Error> {
+    ENV.set(updates)
 }
 
 /// Function to create the .ENV file with the KEYS and PATHS needed.
@@ -80,15 +331,10 @@ pub async fn set_download_path(download_path: &str) -> Result<(), io::Error> {
 pub fn create_env_file() -> Result<(), std::io::Error> {
 
     // Attempt to create the .env file, in case it already exists exit early
-    if std::path::Path::new(".env").exists() {
+    if ENV.path().exists() {
         return Ok(());
     }
 
-    let mut file = std::fs::OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(".env")?;
-
     // Get the KEYS from the environment variables
     let id_client = std::env::var("ID_CLIENT").unwrap();
     let secret = std::env::var("SECRET").unwrap();
@@ -100,12 +346,7 @@ pub fn create_env_file() -> Result<(), std::io::Error> {
     let download_path = download_path_buf.to_str().unwrap();
 
     // Write the API_KEY and DOWNLOAD_PATH to the file
-    file.write_all(format!("ID_CLIENT={}\n", id_client).as_bytes())?;
-    file.write_all(format!("SECRET={}\n", secret).as_bytes())?;
-
-    file.write_all(format!("DOWNLOAD_PATH={}", download_path).as_bytes())?;
-
-    Ok(())
+    set_vars(&[("ID_CLIENT", &id_client), ("SECRET", &secret), ("DOWNLOAD_PATH", download_path)])
 }
 
 
@@ -113,6 +354,39 @@ pub fn create_env_file() -> Result<(), std::io::Error> {
 mod tests {
     use super::*;
 
+    /// Function to create an environment file in the temporary directory.
+    fn env_file(name: &str, contents: &str) -> EnvFile {
+        let path = std::env::temp_dir().join(format!("gameio-env-{}-{}.env", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        EnvFile::open(path)
+    }
+
+    #[test]
+    fn test_env_file_get() {
+        let env = env_file("get", "# settings\nDOWNLOAD_LIMIT=1024\nLIBRARY_PATH=\"/home/user/Games\"\n\n");
+
+        assert_eq!(env.get("DOWNLOAD_LIMIT"), Ok("1024".to_string()));
+        assert_eq!(env.get("LIBRARY_PATH"), Ok("/home/user/Games".to_string()));
+        assert_eq!(env.get("GAMEIO_MISSING_VARIABLE"), Err(VarError::NotPresent));
+
+        fs::remove_file(env.path()).unwrap();
+    }
+
+    #[test]
+    fn test_env_file_set() {
+        let env = env_file("set", "DOWNLOAD_LIMIT=1024\n");
+        env.set(&[("UPLOAD_LIMIT", "512"), ("DOWNLOAD_LIMIT", "0")]).unwrap();
+
+        assert_eq!(env.get("DOWNLOAD_LIMIT"), Ok("0".to_string()));
+
+        // The file is written with the new values
+        let reopened = EnvFile::open(env.path());
+        assert_eq!(reopened.get("DOWNLOAD_LIMIT"), Ok("0".to_string()));
+        assert_eq!(reopened.get("UPLOAD_LIMIT"), Ok("512".to_string()));
+
+        fs::remove_file(env.path()).unwrap();
+    }
+
     #[tokio::test]
     async fn test_get_id_client() {
         let id_client = get_id_client().await.unwrap();
@@ -141,6 +415,69 @@ mod tests {
         assert_eq!(result, ());
     }
 
+    #[tokio::test]
+    async fn test_get_game_download_path() {
+        set_download_path("/home/user/Downloads").await.unwrap();
+        set_path_template(paths::DEFAULT_PATH_TEMPLATE).await.unwrap();
+
+        let path = get_game_download_path("Half-Life: Alyx", "Half-Life.Alyx-FitGirl").await.unwrap();
+
+        assert_eq!(path, "/home/user/Downloads/Half-Life_ Alyx/Half-Life.Alyx-FitGirl");
+    }
+
+    #[tokio::test]
+    async fn test_set_library_path() {
+        set_library_path("/home/user/Games").await.unwrap();
+
+        assert_eq!(get_library_path().await, "/home/user/Games");
+    }
+
+    #[tokio::test]
+    async fn test_set_bandwidth_limits() {
+        set_bandwidth_limits(1024, 512).await.unwrap();
+
+        assert_eq!(get_download_limit().await, 1024);
+        assert_eq!(get_upload_limit().await, 512);
+    }
+
+    #[tokio::test]
+    async fn test_set_max_active_downloads() {
+        set_max_active_downloads(2).await.unwrap();
+
+        assert_eq!(get_max_active_downloads().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_set_seeding_policy() {
+        set_seeding_policy(SeedingPolicy::Duration(3600)).await.unwrap();
+
+        assert_eq!(get_seeding_policy().await, SeedingPolicy::Duration(3600));
+    }
+
+    #[tokio::test]
+    async fn test_set_network_options() {
+        let options = NetworkOptions { listen_port_start: 6881, listen_port_end: 6889, dht: false };
+        set_network_options(options).await.unwrap();
+
+        assert_eq!(get_network_options().await, options);
+    }
+
+    #[tokio::test]
+    async fn test_set_extra_trackers() {
+        let trackers = vec!["udp://tracker.opentrackr.org:1337/announce".to_string()];
+        set_extra_trackers(&trackers, "").await.unwrap();
+
+        assert_eq!(get_extra_trackers().await, trackers);
+    }
+
+    #[tokio::test]
+    async fn test_set_schedule() {
+        let windows = scheduler::parse_schedule("mon-fri@22:00-07:00").unwrap();
+        set_schedule(&windows).await.unwrap();
+
+        assert_eq!(get_schedule().await, windows);
+    }
+
     #[test]
     fn test_create_env_file() {
         let result = create_env_file().unwrap();
@@ -0,0 +1,88 @@
+/*
+This file will be used to add the completed downloads to the library.
+A game is added to the library as soon as its download completes, with the directory of the download as its path.
+If a LIBRARY_PATH is set, the files are moved there once the torrent stops seeding,
+moving them earlier would break the seeding.
+*/
+
+use log::{trace, debug};
+
+use crate::database::Database;
+use crate::env;
+use crate::paths;
+use crate::torrent::TorrentManager;
+
+/// Function to add a completed download to the library.
+/// A download is added only once, adding it again returns its existing entry.
+///
+/// # Arguments
+/// * `db` - The database of the library.
+/// * `download_id` - The id of the download.
+/// * `name` - The name of the download.
+/// * `game` - The name of the game.
+/// * `path` - The directory where the game was downloaded.
+///
+/// # Returns
+/// The id of the library entry of the download.
+///
+pub async fn add_completed_download(db: &Database, download_id: i64, name: &str, game: &str, path: &str) -> Result<i64, anyhow::Error> {
+    trace!("Adding {} to the library", game);
+    let id = db.add_library_entry(Some(download_id), name, game, path).await?;
+    debug!("Library entry {}: {} at {}", id, game, path);
+
+    Ok(id)
+}
+
+/// Function to move the files of a completed download to the LIBRARY_PATH.
+/// Nothing is done if the LIBRARY_PATH is not set.
+/// Flow:
+/// * `Read the names of the files of the torrent and remove it from the session, keeping its files
+/// * `Move the files to the directory resolved from the PATH_TEMPLATE and the LIBRARY_PATH
+/// * `Update the path of the download and of the library entry
+///
+/// # Arguments
+/// * `manager` - The torrent manager of the download.
+/// * `db` - The database of the downloads and of the library.
+/// * `id` - The id of the download.
+/// * `library_id` - The id of the library entry of the download.
+/// * `name` - The name of the download.
+/// * `game` - The name of the game.
+/// * `download_path` - The directory where the game was downloaded.
+///
+pub async fn move_to_library(
+    manager: &TorrentManager,
+    db: &Database,
+    id: i64,
+    library_id: i64,
+    name: &str,
+    game: &str,
+    download_path: &str,
+) -> Result<(), anyhow::Error> {
+    let library_path = env::get_library_path().await;
+    if library_path.is_empty() {
+        return Ok(());
+    }
+
+    let template = env::get_path_template().await;
+    let path = paths::resolve_download_path(&template, &library_path, game, name);
+    trace!("Moving download {} to {}", id, path);
+
+    // The torrent can not use its files once they are moved, the names of the files are read before it leaves the session
+    let file_names = if manager.has_torrent(id) {
+        let file_names = manager.file_names(id)?;
+        manager.cancel(id, false).await?;
+        file_names
+    } else {
+        match db.get_download(id).await? {
+            Some(download) => manager.stored_file_names(&download).await?,
+            None => anyhow::bail!("download {} not found", id),
+        }
+    };
+    manager.move_files(download_path, &path, &file_names)?;
+
+    db.set_download_path(id, &path).await?;
+    db.set_library_path(library_id, &path).await?;
+    debug!("Download {} moved to {}", id, path);
+
+    Ok(())
+}
@@ -0,0 +1,317 @@
+/*
+This file will be used to parse and validate the magnet links.
+It reads the info hash (xt), the name (dn), the trackers (tr) and the length (xl) of a magnet link
+and rebuilds a normalized link from them.
+*/
+
+use std::fmt;
+use std::str::FromStr;
+
+// Prefix of the BitTorrent v1 info hash in the xt parameter
+const BTIH_PREFIX: &str = "urn:btih:";
+
+// Alphabet of the base32 encoded info hashes
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Error returned when a magnet link is malformed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MagnetError {
+    NotAMagnet,
+    MissingInfoHash,
+    InvalidInfoHash(String),
+    InvalidLength(String),
+    InvalidEncoding(String),
+}
+
+impl fmt::Display for MagnetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MagnetError::NotAMagnet => write!(f, "not a magnet link"),
+            MagnetError::MissingInfoHash => write!(f, "missing urn:btih info hash"),
+            MagnetError::InvalidInfoHash(hash) => write!(f, "invalid info hash: {}", hash),
+            MagnetError::InvalidLength(length) => write!(f, "invalid length: {}", length),
+            MagnetError::InvalidEncoding(value) => write!(f, "invalid percent encoding: {}", value),
+        }
+    }
+}
+
+impl std::error::Error for MagnetError {}
+
+/// Parsed magnet link.
+/// The info hash is always stored as 40 lowercase hex characters, whatever the encoding of the link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Magnet {
+    pub info_hash: String,
+    pub name: Option<String>,
+    pub trackers: Vec<String>,
+    pub length: Option<u64>,
+}
+
+impl Magnet {
+
+    /// Function to parse a magnet link.
+    /// Flow:
+    /// * `Check the magnet:? prefix
+    /// * `Split the query in key=value parameters and decode them
+    /// * `Read the first urn:btih info hash, in hex or base32
+    /// * `Read the name, the trackers and the length
+    ///
+    /// # Arguments
+    /// * `link` - A string slice that holds the magnet link.
+    ///
+    pub fn parse(link: &str) -> Result<Self, MagnetError> {
+        let query = link
+            .trim()
+            .strip_prefix("magnet:?")
+            .ok_or(MagnetError::NotAMagnet)?;
+
+        let mut info_hash = None;
+        let mut name = None;
+        let mut trackers: Vec<String> = Vec::new();
+        let mut length = None;
+
+        for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+            let (key, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            let value = percent_decode(value)?;
+
+            match key {
+                "xt" if info_hash.is_none() && value.starts_with(BTIH_PREFIX) => {
+                    info_hash = Some(parse_info_hash(&value[BTIH_PREFIX.len()..])?);
+                }
+                "dn" => name = Some(value),
+                "tr" if !trackers.contains(&value) => trackers.push(value),
+                "xl" => {
+                    let parsed = value
+                        .parse::<u64>()
+                        .map_err(|_| MagnetError::InvalidLength(value.clone()))?;
+                    length = Some(parsed);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            info_hash: info_hash.ok_or(MagnetError::MissingInfoHash)?,
+            name,
+            trackers,
+            length,
+        })
+    }
+
+    /// Function to add trackers to the magnet link.
+    /// Trackers already in the tr parameters are skipped.
+    ///
+    /// # Arguments
+    /// * `trackers` - The trackers to add.
+    ///
+    pub fn add_trackers(&mut self, trackers: &[String]) {
+        for tracker in trackers {
+            if !self.trackers.contains(tracker) {
+                self.trackers.push(tracker.clone());
+            }
+        }
+    }
+
+    /// Function to build the normalized magnet link.
+    /// The info hash is written in lowercase hex and every value is percent encoded.
+    pub fn to_link(&self) -> String {
+        let mut link = format!("magnet:?xt={}{}", BTIH_PREFIX, self.info_hash);
+
+        if let Some(name) = &self.name {
+            link.push_str(&format!("&dn={}", percent_encode(name)));
+        }
+        if let Some(length) = self.length {
+            link.push_str(&format!("&xl={}", length));
+        }
+        for tracker in &self.trackers {
+            link.push_str(&format!("&tr={}", percent_encode(tracker)));
+        }
+
+        link
+    }
+}
+
+impl FromStr for Magnet {
+    type Err = MagnetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Magnet::parse(s)
+    }
+}
+
+impl fmt::Display for Magnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_link())
+    }
+}
+
+/// Function to read a list of trackers, one per line or separated by commas.
+/// Empty lines and lines starting with '#' are ignored, duplicates are removed.
+///
+/// # Arguments
+/// * `text` - The content of the tracker list.
+///
+pub fn parse_tracker_list(text: &str) -> Vec<String> {
+    let mut trackers: Vec<String> = Vec::new();
+
+    for tracker in text
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(|line| line.split(','))
+        .map(str::trim)
+        .filter(|tracker| !tracker.is_empty())
+    {
+        if !trackers.iter().any(|known| known == tracker) {
+            trackers.push(tracker.to_string());
+        }
+    }
+
+    trackers
+}
+
+/// Function to read a v1 info hash, encoded either in hex (40 characters) or in base32 (32 characters).
+///
+/// # Arguments
+/// * `hash` - The info hash, in hex or in base32.
+///
+/// # Returns
+/// The info hash in lowercase hex.
+///
+pub fn parse_info_hash(hash: &str) -> Result<String, MagnetError> {
+    let invalid = || MagnetError::InvalidInfoHash(hash.to_string());
+
+    match hash.len() {
+        40 if hash.chars().all(|c| c.is_ascii_hexdigit()) => Ok(hash.to_ascii_lowercase()),
+        32 => {
+            let bytes = base32_decode(hash).ok_or_else(invalid)?;
+            Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// Function to decode a base32 string without padding.
+/// It returns None if the string contains a character outside of the base32 alphabet.
+fn base32_decode(value: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(value.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for c in value.bytes() {
+        let digit = BASE32_ALPHABET
+            .iter()
+            .position(|&letter| letter == c.to_ascii_uppercase())? as u32;
+
+        buffer = (buffer << 5) | digit;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(bytes)
+}
+
+/// Function to decode a percent encoded query value, '+' is decoded as a space.
+fn percent_decode(value: &str) -> Result<String, MagnetError> {
+    let invalid = || MagnetError::InvalidEncoding(value.to_string());
+    let input = value.as_bytes();
+    let mut bytes = Vec::with_capacity(input.len());
+
+    let mut i = 0;
+    while i < input.len() {
+        match input[i] {
+            b'%' => {
+                let hex = input.get(i + 1..i + 3).ok_or_else(invalid)?;
+                let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+                bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+                i += 3;
+            }
+            b'+' => {
+                bytes.push(b' ');
+                i += 1;
+            }
+            byte => {
+                bytes.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+/// Function to percent encode a query value, only the unreserved characters are kept as they are.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINK: &str = "magnet:?xt=urn:btih:8DF6E26142615621983763B729F640372CF1FC34&dn=Linux+Mint+20.1&tr=udp%3A%2F%2Ftracker.opentrackr.org%3A1337%2Fannounce&tr=http%3A%2F%2Ftracker.openbittorrent.com%3A80%2Fannounce&xl=2048";
+
+    #[test]
+    fn test_parse_hex() {
+        let magnet = Magnet::parse(LINK).unwrap();
+
+        assert_eq!(magnet.info_hash, "8df6e26142615621983763b729f640372cf1fc34");
+        assert_eq!(magnet.name.as_deref(), Some("Linux Mint 20.1"));
+        assert_eq!(magnet.trackers.len(), 2);
+        assert_eq!(magnet.trackers[0], "udp://tracker.opentrackr.org:1337/announce");
+        assert_eq!(magnet.length, Some(2048));
+    }
+
+    #[test]
+    fn test_parse_base32() {
+        let magnet = Magnet::parse("magnet:?xt=urn:btih:RX3OEYKCMFLCDGBXMO3ST5SAG4WPD7BU").unwrap();
+
+        assert_eq!(magnet.info_hash, "8df6e26142615621983763b729f640372cf1fc34");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Magnet::parse("https://1337x.to"), Err(MagnetError::NotAMagnet));
+        assert_eq!(Magnet::parse("magnet:?dn=Zelda"), Err(MagnetError::MissingInfoHash));
+        assert!(matches!(Magnet::parse("magnet:?xt=urn:btih:1234"), Err(MagnetError::InvalidInfoHash(_))));
+        assert!(matches!(Magnet::parse("magnet:?xt=urn:btih:RX3OEYKCMFLCDGBXMO3ST5SAG4WPD7B1"), Err(MagnetError::InvalidInfoHash(_))));
+        assert!(matches!(Magnet::parse(&format!("{}&xl=big", LINK)), Err(MagnetError::InvalidLength(_))));
+        assert!(matches!(Magnet::parse(&format!("{}&dn=%G1", LINK)), Err(MagnetError::InvalidEncoding(_))));
+    }
+
+    #[test]
+    fn test_add_trackers() {
+        let mut magnet = Magnet::parse(LINK).unwrap();
+        magnet.add_trackers(&[
+            "udp://tracker.opentrackr.org:1337/announce".to_string(),
+            "udp://open.stealth.si:80/announce".to_string(),
+        ]);
+
+        assert_eq!(magnet.trackers.len(), 3);
+        assert_eq!(magnet.trackers[2], "udp://open.stealth.si:80/announce");
+    }
+
+    #[test]
+    fn test_parse_tracker_list() {
+        let trackers = parse_tracker_list("# best trackers\nudp://a:80/announce\n\nudp://b:80/announce,udp://a:80/announce\n");
+
+        assert_eq!(trackers, vec!["udp://a:80/announce", "udp://b:80/announce"]);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let magnet = Magnet::parse(LINK).unwrap();
+        let normalized = magnet.to_link();
+
+        assert!(normalized.starts_with("magnet:?xt=urn:btih:8df6e26142615621983763b729f640372cf1fc34&dn=Linux%20Mint%2020.1"));
+        assert_eq!(Magnet::parse(&normalized).unwrap(), magnet);
+    }
+}
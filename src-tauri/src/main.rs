@@ -6,22 +6,45 @@ mod database;
 mod api;
 mod scrapers;
 mod torrent;
+mod queue;
+mod magnet;
+mod scheduler;
+mod paths;
+mod library;
+mod migrations;
 
-use std::thread;
+use tauri::Manager;
+
+use database::DownloadStatus;
 
 /********************************************************************************************************************/
 /// This function will be used to start all the torrents previously started. 
 /// This will be called at the beginning of the application.
-async fn start_torrents() {
-    let torrents = database::get_downloads().await.unwrap();
+/// The state of each download is read from the downloads table:
+/// * `downloading` - put back in the queue, the queue will start them in order of priority
+/// * `seeding` - added back to the session to seed, without taking a slot
+/// * `paused` - left out of the session, the queue adds it back when the user resumes it
+/// * `completed` and `cancelled` - not restarted
+/// * `error` - retried by the queue when its retry time comes
+async fn start_torrents(db: &database::Database, manager: &torrent::TorrentManager) {
+    let torrents = match db.get_downloads().await {
+        Ok(torrents) => torrents,
+        Err(e) => {
+            log::error!("error getting downloads: {:?}", e);
+            return;
+        }
+    };
 
     for torrent in torrents {
-        let download_path = torrent["path"].as_str().unwrap().to_string(); // Convert to owned String
-        let magnet_link: String = torrent["link"].as_str().unwrap().to_string(); // Convert to owned String
-
-        thread::spawn(move || {
-            torrent::download_torrent(&download_path, &magnet_link); // Directly use the owned Strings
-        });
+        match torrent.status {
+            DownloadStatus::Downloading => {
+                if let Err(e) = db.set_download_status(torrent.id, DownloadStatus::Queued).await {
+                    log::error!("error updating download {}: {:?}", torrent.id, e);
+                }
+            }
+            DownloadStatus::Seeding => queue::restore_seeding(manager, db, &torrent),
+            _ => {}
+        }
     }
 }
 /********************************************************************************************************************/
@@ -70,6 +93,18 @@ async fn game_details(game_id: i64) -> Result<serde_json::Value, String> {
 }
 /********************************************************************************************************************/
 
+/********************************************************************************************************************/
+/// This function will be used to refuse a torrent that is already in the downloads.
+/// Downloads are identified by the info hash of their torrent.
+async fn check_not_downloading(db: &database::Database, info_hash: &str) -> Result<(), String> {
+    match db.find_download_by_info_hash(info_hash).await {
+        Ok(Some(id)) => Err(format!("Torrent already in the downloads (id {})", id)),
+        Ok(None) => Ok(()),
+        Err(e) => Err(format!("Failed to get downloads: {}", e)),
+    }
+}
+/********************************************************************************************************************/
+
 /********************************************************************************************************************/
 /// Gets the list of torrents for a game
 #[tauri::command]
@@ -80,35 +115,695 @@ async fn get_torrents(game_name: &str) -> Result<Vec<(String, String)>, String>
 /********************************************************************************************************************/
 
 /********************************************************************************************************************/
-/// Gets the magnet link of a torrent and downloads it
+/// Gets the magnet link of a torrent and returns the list of its files
+#[tauri::command]
+async fn get_torrent_files(manager: tauri::State<'_, torrent::TorrentManager>, url: &str) -> Result<Vec<torrent::TorrentFile>, String> {
+
+    // Get the magnet link of the torrent
+    let magnet_link = match scrapers::get_magnet_link(url).await {
+        Ok(link) => link,
+        Err(e) => return Err(format!("Failed to get magnet link: {}", e)),
+    };
+
+    // Validate and normalize the magnet link
+    let magnet = match magnet::Magnet::parse(&magnet_link) {
+        Ok(magnet) => magnet,
+        Err(e) => return Err(format!("Invalid magnet link: {}", e)),
+    };
+
+    // Resolve the metadata of the torrent, the extra trackers help to find peers that have it
+    let link = queue::add_extra_trackers(&magnet.to_link()).await;
+    match manager.list_files(&link).await {
+        Ok(files) => Ok(files),
+        Err(e) => Err(format!("Failed to get torrent files: {}", e)),
+    }
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Gets the magnet link of a torrent and adds it to the download queue
+/// Only the files whose indices are in files are downloaded, null downloads the whole torrent
 #[tauri::command]
-async fn download_torrent(name : &str, game: &str, url: &str, uploader: &str) -> Result<(), String> {
+async fn download_torrent(db: tauri::State<'_, database::Database>, name : &str, game: &str, url: &str, uploader: &str, files: Option<Vec<usize>>) -> Result<(), String> {
 
     // Get the magnet link of the torrent
-    print!("Getting magnet link... of {}", url);
-    let magnet_link: String = scrapers::get_magnet_link(url).await.unwrap();
-    print!("{}", magnet_link);
+    let magnet_link = match scrapers::get_magnet_link(url).await {
+        Ok(link) => link,
+        Err(e) => return Err(format!("Failed to get magnet link: {}", e)),
+    };
+
+    // Validate and normalize the magnet link
+    let magnet = match magnet::Magnet::parse(&magnet_link) {
+        Ok(magnet) => magnet,
+        Err(e) => return Err(format!("Invalid magnet link: {}", e)),
+    };
+
+    // Refuse the download if the torrent is already in the downloads
+    check_not_downloading(&db, &magnet.info_hash).await?;
 
     // Add the download to the database
     let name = name;
     let game = game;
-    let link = url;
+    let link = magnet.to_link();
     let uploader = uploader;
-    let path = env::get_download_path().await.unwrap();
-    database::add_download(name, game, &link, uploader, &path).await.unwrap();
+    let path = match env::get_game_download_path(game, name).await {
+        Ok(path) => path,
+        Err(e) => return Err(format!("Failed to get download path: {}", e)),
+    };
+    if let Err(e) = db.add_download(name, game, &link, uploader, &path, &magnet.info_hash, files.as_deref()).await {
+        return Err(format!("Failed to add download: {}", e));
+    }
+
+    // Let the queue start the download when a slot is free
+    queue::notify();
+
+    Ok(())
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Imports a local .torrent file and adds it to the download queue
+/// The file is given either by its path or by its raw bytes, the name of the download is read from the file
+/// Only the files whose indices are in files are downloaded, null downloads the whole torrent
+#[tauri::command]
+async fn import_torrent_file(db: tauri::State<'_, database::Database>, game: &str, uploader: &str, file_path: Option<String>, bytes: Option<Vec<u8>>, files: Option<Vec<usize>>) -> Result<(), String> {
+
+    // Read the content of the .torrent file
+    let bytes = match (bytes, file_path) {
+        (Some(bytes), _) => bytes,
+        (None, Some(file_path)) => match std::fs::read(&file_path) {
+            Ok(bytes) => bytes,
+            Err(e) => return Err(format!("Failed to read torrent file: {}", e)),
+        },
+        (None, None) => return Err("No torrent file given".to_string()),
+    };
+
+    // Read the info hash and the name from the metainfo
+    let info = match torrent::read_torrent_file(&bytes) {
+        Ok(info) => info,
+        Err(e) => return Err(format!("Invalid torrent file: {}", e)),
+    };
+
+    // Refuse the download if the torrent is already in the downloads
+    check_not_downloading(&db, &info.info_hash).await?;
+
+    // Keep a copy of the file, it is needed to restart the download
+    let link = match torrent::save_torrent_file(&bytes, &info.info_hash) {
+        Ok(link) => link,
+        Err(e) => return Err(format!("Failed to save torrent file: {}", e)),
+    };
+
+    // Add the download to the database
+    let path = match env::get_game_download_path(game, &info.name).await {
+        Ok(path) => path,
+        Err(e) => return Err(format!("Failed to get download path: {}", e)),
+    };
+    if let Err(e) = db.add_download(&info.name, game, &link, uploader, &path, &info.info_hash, files.as_deref()).await {
+        return Err(format!("Failed to add download: {}", e));
+    }
+
+    // Let the queue start the download when a slot is free
+    queue::notify();
+
+    Ok(())
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Pauses a queued or active download
+#[tauri::command]
+async fn pause_download(manager: tauri::State<'_, torrent::TorrentManager>, db: tauri::State<'_, database::Database>, id: i64) -> Result<(), String> {
+
+    // Only the queued and the active downloads can be paused
+    match db.get_download(id).await {
+        Ok(Some(download)) if matches!(download.status, DownloadStatus::Queued | DownloadStatus::Downloading) => {}
+        Ok(Some(_)) => return Err("Download is not queued or downloading".to_string()),
+        Ok(None) => return Err("Download not found".to_string()),
+        Err(e) => return Err(format!("Failed to get download: {}", e)),
+    }
+
+    // Update the status of the download in the database
+    if let Err(e) = db.set_download_status(id, DownloadStatus::Paused).await {
+        return Err(format!("Failed to update download: {}", e));
+    }
+
+    // Pause the torrent in the session, queued downloads are not in the session yet
+    if manager.has_torrent(id) {
+        if let Err(e) = manager.pause(id).await {
+            return Err(format!("Failed to pause download: {}", e));
+        }
+    }
+
+    // The slot of the download is now free
+    queue::notify();
+
+    Ok(())
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Resumes a paused download, it is put back in the queue and resumed when a slot is free
+#[tauri::command]
+async fn resume_download(db: tauri::State<'_, database::Database>, id: i64) -> Result<(), String> {
+
+    // Only the paused downloads can be resumed
+    match db.get_download(id).await {
+        Ok(Some(download)) if download.status == DownloadStatus::Paused => {}
+        Ok(Some(_)) => return Err("Download is not paused".to_string()),
+        Ok(None) => return Err("Download not found".to_string()),
+        Err(e) => return Err(format!("Failed to get download: {}", e)),
+    }
+
+    // Update the status of the download in the database
+    if let Err(e) = db.set_download_status(id, DownloadStatus::Queued).await {
+        return Err(format!("Failed to update download: {}", e));
+    }
+
+    // Let the queue resume the download
+    queue::notify();
+
+    Ok(())
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Retries a failed download now, without waiting for the next automatic retry
+/// Its error and attempts are cleared and it is put back in the queue
+#[tauri::command]
+async fn retry_download(db: tauri::State<'_, database::Database>, id: i64) -> Result<(), String> {
+
+    // Only the failed downloads can be retried
+    match db.get_download(id).await {
+        Ok(Some(download)) if download.status == DownloadStatus::Error => {}
+        Ok(Some(_)) => return Err("Download has not failed".to_string()),
+        Ok(None) => return Err("Download not found".to_string()),
+        Err(e) => return Err(format!("Failed to get download: {}", e)),
+    }
+
+    // Put the download back in the queue
+    if let Err(e) = db.clear_download_error(id).await {
+        return Err(format!("Failed to update download: {}", e));
+    }
+    if let Err(e) = db.set_download_status(id, DownloadStatus::Queued).await {
+        return Err(format!("Failed to update download: {}", e));
+    }
+
+    // Let the queue start the download when a slot is free
+    queue::notify();
+
+    Ok(())
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Cancels a download, optionally deleting the downloaded files
+#[tauri::command]
+async fn cancel_download(manager: tauri::State<'_, torrent::TorrentManager>, db: tauri::State<'_, database::Database>, id: i64, delete_files: bool) -> Result<(), String> {
+
+    // The completed and the cancelled downloads can not be cancelled
+    let download = match db.get_download(id).await {
+        Ok(Some(download)) if !matches!(download.status, DownloadStatus::Completed | DownloadStatus::Cancelled) => download,
+        Ok(Some(_)) => return Err("Download is already completed or cancelled".to_string()),
+        Ok(None) => return Err("Download not found".to_string()),
+        Err(e) => return Err(format!("Failed to get download: {}", e)),
+    };
+
+    // Update the status of the download in the database
+    if let Err(e) = db.set_download_status(id, DownloadStatus::Cancelled).await {
+        return Err(format!("Failed to update download: {}", e));
+    }
+
+    // Remove the torrent from the session, the paused, failed and queued downloads are not in the session
+    if manager.has_torrent(id) {
+        if let Err(e) = manager.cancel(id, delete_files).await {
+            return Err(format!("Failed to cancel download: {}", e));
+        }
+    } else if delete_files {
+        let file_names = match manager.stored_file_names(&download).await {
+            Ok(file_names) => file_names,
+            Err(e) => return Err(format!("Failed to get torrent files: {}", e)),
+        };
+        if let Err(e) = manager.delete_files(&download.path, &file_names) {
+            return Err(format!("Failed to delete files: {}", e));
+        }
+    }
+
+    // The slot of the download is now free
+    queue::notify();
+
+    Ok(())
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Removes a download from the downloads, it is given either by its id or by the info hash of its torrent
+/// The torrent is stopped and removed from the session, the downloaded files are deleted if delete_files is true
+#[tauri::command]
+async fn remove_download(manager: tauri::State<'_, torrent::TorrentManager>, db: tauri::State<'_, database::Database>, id: Option<i64>, info_hash: Option<String>, delete_files: bool) -> Result<(), String> {
+
+    // Find the id of the download
+    let id = match (id, info_hash) {
+        (Some(id), _) => id,
+        (None, Some(info_hash)) => {
+            // The info hashes are stored in lowercase hex, the base32 ones are converted
+            let info_hash = match magnet::parse_info_hash(&info_hash) {
+                Ok(info_hash) => info_hash,
+                Err(e) => return Err(format!("Failed to find download: {}", e)),
+            };
+            match db.find_download_by_info_hash(&info_hash).await {
+                Ok(Some(id)) => id,
+                Ok(None) => return Err("Download not found".to_string()),
+                Err(e) => return Err(format!("Failed to find download: {}", e)),
+            }
+        }
+        (None, None) => return Err("No download given".to_string()),
+    };
+
+    // Get the row of the download
+    let download = match db.get_download(id).await {
+        Ok(Some(download)) => download,
+        Ok(None) => return Err("Download not found".to_string()),
+        Err(e) => return Err(format!("Failed to get download: {}", e)),
+    };
+    let path = &download.path;
+    let link = &download.link;
+
+    // The files of a completed download are the game installed in the library, they are deleted from the library
+    if delete_files {
+        match db.find_library_entry_by_download(id).await {
+            Ok(None) => {}
+            Ok(Some(_)) => return Err("Download is installed in the library, remove it from the library first".to_string()),
+            Err(e) => return Err(format!("Failed to get library entry: {}", e)),
+        }
+    }
+
+    // Stop the torrent and drop its handle, the engine deletes the files of the torrents in the session
+    if manager.has_torrent(id) {
+        if let Err(e) = manager.cancel(id, delete_files).await {
+            return Err(format!("Failed to remove download: {}", e));
+        }
+    } else if delete_files {
+        let file_names = match manager.stored_file_names(&download).await {
+            Ok(file_names) => file_names,
+            Err(e) => return Err(format!("Failed to get torrent files: {}", e)),
+        };
+        if let Err(e) = manager.delete_files(path, &file_names) {
+            return Err(format!("Failed to delete files: {}", e));
+        }
+    }
+
+    // Remove the download from the database with its copy of the .torrent file
+    if let Err(e) = db.remove_download(id).await {
+        return Err(format!("Failed to remove download: {}", e));
+    }
+    if let Err(e) = torrent::remove_torrent_file(link) {
+        return Err(format!("Failed to delete torrent file: {}", e));
+    }
+
+    // The slot of the download is now free
+    queue::notify();
+
+    Ok(())
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Inspects an active download, it returns its live peers, its trackers and the state of the DHT
+/// It will be used to understand why a download is stalled
+#[tauri::command]
+async fn inspect_download(manager: tauri::State<'_, torrent::TorrentManager>, db: tauri::State<'_, database::Database>, id: i64) -> Result<torrent::DownloadInspection, String> {
+
+    // Get the row of the download
+    let download = match db.get_download(id).await {
+        Ok(Some(download)) => download,
+        Ok(None) => return Err("Download not found".to_string()),
+        Err(e) => return Err(format!("Failed to get download: {}", e)),
+    };
+    if !manager.has_torrent(id) {
+        return Err("Download is not active".to_string());
+    }
+
+    match manager.inspect(download.id) {
+        Ok(inspection) => Ok(inspection),
+        Err(e) => Err(format!("Failed to inspect download: {}", e)),
+    }
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Gets all the downloads ordered by their position in the queue
+#[tauri::command]
+async fn get_downloads(db: tauri::State<'_, database::Database>) -> Result<Vec<database::Download>, String> {
+    match db.get_downloads().await {
+        Ok(downloads) => Ok(downloads),
+        Err(e) => Err(format!("Failed to get downloads: {}", e)),
+    }
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Updates the priority of a download in the queue, lower priorities start first
+#[tauri::command]
+async fn set_download_priority(db: tauri::State<'_, database::Database>, id: i64, priority: i64) -> Result<(), String> {
+    if let Err(e) = db.set_download_priority(id, priority).await {
+        return Err(format!("Failed to update download: {}", e));
+    }
+
+    queue::notify();
+
+    Ok(())
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Moves a download to the top of the queue
+#[tauri::command]
+async fn move_download_to_top(db: tauri::State<'_, database::Database>, id: i64) -> Result<(), String> {
+    if let Err(e) = db.move_download_to_top(id).await {
+        return Err(format!("Failed to update download: {}", e));
+    }
+
+    queue::notify();
+
+    Ok(())
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Gets the number of downloads that can run at the same time
+#[tauri::command]
+async fn get_max_active_downloads() -> Result<usize, String> {
+    Ok(env::get_max_active_downloads().await)
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Updates the number of downloads that can run at the same time
+#[tauri::command]
+async fn set_max_active_downloads(max_active_downloads: usize) -> Result<(), String> {
+    if let Err(e) = env::set_max_active_downloads(max_active_downloads).await {
+        return Err(format!("Failed to save max active downloads: {}", e));
+    }
+
+    queue::notify();
 
-    // Get the download path
-    let download_path = env::get_download_path().await.unwrap();
+    Ok(())
+}
+/********************************************************************************************************************/
 
-    // Start the torrent download as a new thread
-    thread::spawn(move || {
-        torrent::download_torrent(&download_path, &magnet_link); // Directly use the owned Strings
+/********************************************************************************************************************/
+/// Gets the progress of all the active downloads
+#[tauri::command]
+async fn get_download_progress() -> Result<Vec<torrent::DownloadProgress>, String> {
+    Ok(torrent::get_download_progress().await)
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Gets the global bandwidth limits, in bytes per second (0 means unlimited)
+#[tauri::command]
+async fn get_bandwidth_limits() -> Result<torrent::BandwidthLimits, String> {
+    Ok(torrent::BandwidthLimits {
+        download: Some(env::get_download_limit().await),
+        upload: Some(env::get_upload_limit().await),
+    })
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Updates the global bandwidth limits, in bytes per second (0 means unlimited)
+#[tauri::command]
+async fn set_bandwidth_limits(manager: tauri::State<'_, torrent::TorrentManager>, download_limit: u32, upload_limit: u32) -> Result<(), String> {
+
+    // Store the limits in the .env file
+    if let Err(e) = env::set_bandwidth_limits(download_limit, upload_limit).await {
+        return Err(format!("Failed to save bandwidth limits: {}", e));
+    }
+
+    // Apply the limits to the session
+    manager.set_global_limits(torrent::BandwidthLimits {
+        download: Some(download_limit),
+        upload: Some(upload_limit),
     });
 
     Ok(())
 }
 /********************************************************************************************************************/
 
+/********************************************************************************************************************/
+/// Updates the bandwidth limits of a single download, null removes the override
+#[tauri::command]
+async fn set_download_bandwidth_limits(manager: tauri::State<'_, torrent::TorrentManager>, db: tauri::State<'_, database::Database>, id: i64, download_limit: Option<u32>, upload_limit: Option<u32>) -> Result<(), String> {
+
+    // Store the limits in the database
+    if let Err(e) = db.set_download_limits(id, download_limit, upload_limit).await {
+        return Err(format!("Failed to update download: {}", e));
+    }
+
+    // Apply the limits to the torrent, queued downloads will get them when they start
+    let limits = torrent::BandwidthLimits {
+        download: download_limit,
+        upload: upload_limit,
+    };
+    if manager.has_torrent(id) {
+        if let Err(e) = manager.set_download_limits(id, limits) {
+            return Err(format!("Failed to apply bandwidth limits: {}", e));
+        }
+    }
+
+    Ok(())
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Gets the global seeding policy
+#[tauri::command]
+async fn get_seeding_policy() -> Result<torrent::SeedingPolicy, String> {
+    Ok(env::get_seeding_policy().await)
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Updates the global seeding policy, it applies to the downloads without their own policy
+#[tauri::command]
+async fn set_seeding_policy(seeding_policy: torrent::SeedingPolicy) -> Result<(), String> {
+    if let Err(e) = env::set_seeding_policy(seeding_policy).await {
+        return Err(format!("Failed to save seeding policy: {}", e));
+    }
+
+    Ok(())
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Updates the seeding policy of a single download, null removes the override
+/// The new policy is used the next time the download starts seeding
+#[tauri::command]
+async fn set_download_seeding_policy(db: tauri::State<'_, database::Database>, id: i64, seeding_policy: Option<torrent::SeedingPolicy>) -> Result<(), String> {
+    let seeding_policy = seeding_policy.map(|policy| policy.to_string());
+    if let Err(e) = db.set_download_seeding_policy(id, seeding_policy.as_deref()).await {
+        return Err(format!("Failed to update download: {}", e));
+    }
+
+    Ok(())
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Gets the extra trackers added to the magnet links and the path of the tracker list file
+#[tauri::command]
+async fn get_extra_trackers() -> Result<(Vec<String>, String), String> {
+    Ok((env::get_extra_trackers().await, env::get_extra_trackers_file().await))
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Updates the extra trackers added to the magnet links, file_path is an optional local tracker list
+#[tauri::command]
+async fn set_extra_trackers(trackers: Vec<String>, file_path: Option<String>) -> Result<(), String> {
+    let file_path = file_path.unwrap_or_default();
+    if let Err(e) = env::set_extra_trackers(&trackers, &file_path).await {
+        return Err(format!("Failed to save extra trackers: {}", e));
+    }
+
+    Ok(())
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Enables or disables the extra trackers for a single download
+/// The change is used the next time the download starts
+#[tauri::command]
+async fn set_download_extra_trackers(db: tauri::State<'_, database::Database>, id: i64, enabled: bool) -> Result<(), String> {
+    if let Err(e) = db.set_download_extra_trackers(id, enabled).await {
+        return Err(format!("Failed to update download: {}", e));
+    }
+
+    Ok(())
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Gets the weekly windows in which the downloads can run
+#[tauri::command]
+async fn get_schedule() -> Result<Vec<scheduler::ScheduleWindow>, String> {
+    Ok(env::get_schedule().await)
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Updates the weekly windows in which the downloads can run, an empty list always runs them
+#[tauri::command]
+async fn set_schedule(windows: Vec<scheduler::ScheduleWindow>) -> Result<(), String> {
+
+    // Refuse the days out of the week, they would be taken for other days
+    if let Some(day) = windows.iter().flat_map(|window| window.days.iter()).find(|day| **day > 6) {
+        return Err(format!("Invalid schedule: day {} is not between 0 (Monday) and 6 (Sunday)", day));
+    }
+
+    // Refuse windows that could not be read back from the .env file
+    let schedule = scheduler::format_schedule(&windows);
+    if let Err(e) = scheduler::parse_schedule(&schedule) {
+        return Err(format!("Invalid schedule: {}", e));
+    }
+
+    if let Err(e) = env::set_schedule(&windows).await {
+        return Err(format!("Failed to save schedule: {}", e));
+    }
+
+    Ok(())
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Gets the current mode of the scheduler
+#[tauri::command]
+async fn get_scheduler_mode() -> Result<scheduler::SchedulerMode, String> {
+    Ok(scheduler::get_mode())
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Returns the configured network options with the port the session listens on and whether the DHT is running
+#[tauri::command]
+async fn get_network_settings(manager: tauri::State<'_, torrent::TorrentManager>) -> Result<torrent::NetworkStatus, String> {
+    let options = env::get_network_options().await;
+
+    Ok(manager.network_status(options))
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Updates the listen port range and the DHT toggle, they are applied on the next start of the application
+#[tauri::command]
+async fn set_network_settings(options: torrent::NetworkOptions) -> Result<(), String> {
+    if options.listen_port_range().is_none() {
+        return Err("Invalid listen port range, the ports must be between 1 and 65534".to_string());
+    }
+
+    if let Err(e) = env::set_network_options(options).await {
+        return Err(format!("Failed to update network settings: {}", e));
+    }
+
+    Ok(())
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Returns the template of the download directories, e.g. {download_path}/{game}/{torrent_name}
+#[tauri::command]
+async fn get_path_template() -> Result<String, String> {
+    Ok(env::get_path_template().await)
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Updates the template of the download directories, it applies to the next downloads
+/// The {download_path}, {game} and {torrent_name} placeholders are filled when a download is added
+#[tauri::command]
+async fn set_path_template(path_template: &str) -> Result<(), String> {
+    if let Err(e) = env::set_path_template(path_template).await {
+        return Err(format!("Failed to update path template: {}", e));
+    }
+
+    Ok(())
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Gets all the installed games of the library, ordered by game
+#[tauri::command]
+async fn get_library_entries(db: tauri::State<'_, database::Database>) -> Result<Vec<database::LibraryEntry>, String> {
+    match db.get_library().await {
+        Ok(library) => Ok(library),
+        Err(e) => Err(format!("Failed to get library: {}", e)),
+    }
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Gets an installed game of the library
+#[tauri::command]
+async fn get_library_entry(db: tauri::State<'_, database::Database>, id: i64) -> Result<database::LibraryEntry, String> {
+    match db.get_library_entry(id).await {
+        Ok(Some(entry)) => Ok(entry),
+        Ok(None) => Err("Library entry not found".to_string()),
+        Err(e) => Err(format!("Failed to get library entry: {}", e)),
+    }
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Updates an installed game of the library, e.g. to choose its executable
+/// The executable must be an existing file
+#[tauri::command]
+async fn update_library_entry(db: tauri::State<'_, database::Database>, entry: database::LibraryEntry) -> Result<(), String> {
+    if let Some(executable) = &entry.executable {
+        if !std::path::Path::new(executable).is_file() {
+            return Err("Executable not found".to_string());
+        }
+    }
+
+    match db.update_library_entry(&entry).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err("Library entry not found".to_string()),
+        Err(e) => Err(format!("Failed to update library entry: {}", e)),
+    }
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Removes a game from the library, its files are kept on disk
+#[tauri::command]
+async fn remove_library_entry(db: tauri::State<'_, database::Database>, id: i64) -> Result<(), String> {
+    match db.remove_library_entry(id).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err("Library entry not found".to_string()),
+        Err(e) => Err(format!("Failed to remove library entry: {}", e)),
+    }
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Returns the directory where the completed downloads are moved, empty if they stay in the download path
+#[tauri::command]
+async fn get_library_path() -> Result<String, String> {
+    Ok(env::get_library_path().await)
+}
+/********************************************************************************************************************/
+
+/********************************************************************************************************************/
+/// Updates the directory where the completed downloads are moved, empty to keep them in the download path
+#[tauri::command]
+async fn set_library_path(library_path: &str) -> Result<(), String> {
+    if let Err(e) = env::set_library_path(library_path).await {
+        return Err(format!("Failed to update library path: {}", e));
+    }
+
+    Ok(())
+}
+/********************************************************************************************************************/
+
 /********************************************************************************************************************/
 /// Update the downloaded path
 #[tauri::command]
@@ -127,21 +822,91 @@ fn main() {
     // Create the .env file
     env::create_env_file().unwrap();
 
-    // Attempt to create the database.sqlite file
-    database::create_database_sqlite();
+    // Open the database.sqlite file, its connection is shared by the commands and the background tasks
+    let db = database::Database::open("database.sqlite").expect("error while opening database.sqlite");
 
-    // Call the start_torrents function
-    tokio::runtime::Runtime::new().unwrap().block_on(start_torrents());
+    // Create the shared torrent session and put the previous torrents back in the queue
+    let manager = tauri::async_runtime::block_on(async {
+        let download_path = env::get_download_path().await.unwrap();
+        let limits = torrent::BandwidthLimits {
+            download: Some(env::get_download_limit().await),
+            upload: Some(env::get_upload_limit().await),
+        };
+        let network = env::get_network_options().await;
+        let manager = torrent::TorrentManager::new(&download_path, limits, network)
+            .await
+            .expect("error while creating the torrent session");
+        start_torrents(&db, &manager).await;
+        manager
+    });
 
     // Run the tauri application
     tauri::Builder::default()
+        .manage(manager)
+        .manage(db)
+        .setup(|app| {
+            // Push the progress of the downloads to the frontend
+            tauri::async_runtime::spawn(torrent::emit_progress(app.handle()));
+
+            // Start the queued downloads when a slot is free
+            let manager = app.state::<torrent::TorrentManager>().inner().clone();
+            let db = app.state::<database::Database>().inner().clone();
+            tauri::async_runtime::spawn(queue::run(manager.clone(), db.clone()));
+
+            // Pause and resume the downloads following the schedule
+            tauri::async_runtime::spawn(scheduler::run(manager, db, app.handle()));
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             games_list,
             game_details,
             get_torrents,
+            get_torrent_files,
             download_torrent,
+            import_torrent_file,
+            pause_download,
+            resume_download,
+            retry_download,
+            cancel_download,
+            remove_download,
+            get_downloads,
+            set_download_priority,
+            move_download_to_top,
+            get_max_active_downloads,
+            set_max_active_downloads,
+            get_download_progress,
+            inspect_download,
+            get_bandwidth_limits,
+            set_bandwidth_limits,
+            set_download_bandwidth_limits,
+            get_seeding_policy,
+            set_seeding_policy,
+            set_download_seeding_policy,
+            get_extra_trackers,
+            set_extra_trackers,
+            set_download_extra_trackers,
+            get_schedule,
+            set_schedule,
+            get_scheduler_mode,
+            get_network_settings,
+            set_network_settings,
+            get_path_template,
+            set_path_template,
+            get_library_entries,
+            get_library_entry,
+            update_library_entry,
+            remove_library_entry,
+            get_library_path,
+            set_library_path,
             set_downloaded_path
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app_handle, event| {
+            // Stop the torrent session before the application exits
+            if let tauri::RunEvent::Exit = event {
+                let manager = app_handle.state::<torrent::TorrentManager>();
+                tauri::async_runtime::block_on(manager.stop());
+            }
+        });
 }
@@ -0,0 +1,522 @@
+/*
+This file will be used to migrate the database.sqlite file to the latest schema.
+The version of the schema is stored in PRAGMA user_version, it is the number of migrations applied.
+Each migration runs in its own transaction with the update of the version,
+so a failed migration leaves the database at the previous version.
+New migrations are appended to MIGRATIONS, the existing ones must never be changed.
+*/
+
+use rusqlite::{Connection, Error as RusqliteError, Transaction, TransactionBehavior};
+use log::{trace, debug, error};
+
+type Migration = fn(&Transaction) -> Result<(), RusqliteError>;
+
+// Ordered list of the migrations, the migration at index i moves the schema from version i to version i + 1
+const MIGRATIONS: &[Migration] = &[
+    create_tables,
+    add_download_columns,
+    add_download_keys,
+    add_library_keys,
+    add_download_file_names,
+    add_library_download_id,
+    add_download_seeding,
+    park_web_links,
+];
+
+/// Function to get the version of the schema once all the migrations are applied.
+pub fn latest_version() -> u32 {
+    MIGRATIONS.len() as u32
+}
+
+/// Function to get the version of the schema of a database.
+///
+/// # Arguments
+/// * `conn` - The connection to the database.
+///
+pub fn schema_version(conn: &Connection) -> Result<u32, RusqliteError> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// Function to apply all the pending migrations to a database.
+/// It will be called at the beginning of the application.
+///
+/// # Arguments
+/// * `conn` - The connection to the database.
+///
+/// # Returns
+/// The version of the schema after the migrations.
+///
+pub fn migrate(conn: &mut Connection) -> Result<u32, RusqliteError> {
+    migrate_to(conn, latest_version())
+}
+
+/// Function to apply the pending migrations up to a version.
+/// A database with a newer schema, written by a newer version of the application, is left untouched.
+/// Each migration takes the write lock before reading the version, so two connections opening the
+/// same database at the same time can not apply the same migration twice.
+///
+/// # Arguments
+/// * `conn` - The connection to the database.
+/// * `target` - The version of the schema to reach.
+///
+fn migrate_to(conn: &mut Connection, target: u32) -> Result<u32, RusqliteError> {
+    loop {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        // The version is read inside the transaction, another connection may have migrated the database meanwhile
+        let version = schema_version(&tx)?;
+        debug!("Database schema version: {}, target: {}", version, target);
+        if version > latest_version() {
+            error!("database schema version {} is newer than the application ({})", version, latest_version());
+            return Ok(version);
+        }
+        if version >= target {
+            return Ok(version);
+        }
+
+        let next = version + 1;
+        trace!("Migrating database to version {}", next);
+
+        MIGRATIONS[version as usize](&tx)?;
+        tx.pragma_update(None, "user_version", next)?;
+        tx.commit()?;
+    }
+}
+
+/// Function to add a column to a table unless it already exists.
+/// Databases created before the migrations may already have some of the columns.
+///
+/// # Arguments
+/// * `tx` - The transaction of the migration.
+/// * `table` - The name of the table.
+/// * `column` - The name of the column.
+/// * `definition` - The type and the constraints of the column.
+///
+fn add_column(tx: &Transaction, table: &str, column: &str, definition: &str) -> Result<(), RusqliteError> {
+    let exists: bool = tx.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+        rusqlite::params![table, column],
+        |row| row.get(0),
+    )?;
+
+    if !exists {
+        tx.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])?;
+    }
+
+    Ok(())
+}
+
+/// Migration 1: create the downloads and library tables as the first versions of the application did.
+fn create_tables(tx: &Transaction) -> Result<(), RusqliteError> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS downloads (
+            name TEXT,
+            game TEXT,
+            link TEXT,
+            uploader TEXT,
+            path TEXT
+        )",
+        [],
+    )?;
+
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS library (
+            name TEXT,
+            game TEXT,
+            path TEXT,
+            executable TEXT
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Migration 2: add the queue, bandwidth, file selection, seeding, tracker and retry columns to the downloads.
+fn add_download_columns(tx: &Transaction) -> Result<(), RusqliteError> {
+    add_column(tx, "downloads", "status", "TEXT DEFAULT 'queued'")?;
+    add_column(tx, "downloads", "priority", "INTEGER DEFAULT 0")?;
+    add_column(tx, "downloads", "download_limit", "INTEGER")?;
+    add_column(tx, "downloads", "upload_limit", "INTEGER")?;
+    add_column(tx, "downloads", "files", "TEXT")?;
+    add_column(tx, "downloads", "info_hash", "TEXT")?;
+    add_column(tx, "downloads", "seeding_policy", "TEXT")?;
+    add_column(tx, "downloads", "extra_trackers", "INTEGER DEFAULT 1")?;
+    add_column(tx, "downloads", "error", "TEXT")?;
+    add_column(tx, "downloads", "attempts", "INTEGER DEFAULT 0")?;
+    add_column(tx, "downloads", "retry_at", "INTEGER")?;
+
+    // Keep the order in which the downloads were added
+    tx.execute("UPDATE downloads SET priority = rowid WHERE priority = 0", [])?;
+
+    Ok(())
+}
+
+/// Migration 3: rebuild the downloads table with an integer primary key, a unique info hash,
+/// a checked status, the size and progress of the download and its timestamps.
+/// The ids are kept from the rowids, so the torrents keep their id.
+/// Only the newest download of a torrent keeps its info hash.
+fn add_download_keys(tx: &Transaction) -> Result<(), RusqliteError> {
+    tx.execute_batch(
+        "CREATE TABLE downloads_new (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            game TEXT NOT NULL,
+            link TEXT NOT NULL,
+            uploader TEXT NOT NULL,
+            path TEXT NOT NULL,
+            info_hash TEXT UNIQUE,
+            status TEXT NOT NULL DEFAULT 'queued'
+                CHECK (status IN ('queued', 'downloading', 'paused', 'seeding', 'completed', 'cancelled', 'error')),
+            priority INTEGER NOT NULL DEFAULT 0,
+            download_limit INTEGER,
+            upload_limit INTEGER,
+            files TEXT,
+            seeding_policy TEXT,
+            extra_trackers INTEGER NOT NULL DEFAULT 1,
+            error TEXT,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            retry_at INTEGER,
+            size INTEGER,
+            bytes_done INTEGER NOT NULL DEFAULT 0,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            completed_at INTEGER
+        );
+
+        INSERT INTO downloads_new (
+            id, name, game, link, uploader, path, info_hash, status, priority, download_limit, upload_limit,
+            files, seeding_policy, extra_trackers, error, attempts, retry_at
+        )
+        SELECT
+            rowid,
+            COALESCE(name, ''),
+            COALESCE(game, ''),
+            COALESCE(link, ''),
+            COALESCE(uploader, ''),
+            COALESCE(path, ''),
+            CASE WHEN rowid = (SELECT MAX(other.rowid) FROM downloads other WHERE other.info_hash = downloads.info_hash)
+                THEN info_hash END,
+            CASE WHEN status IN ('queued', 'downloading', 'paused', 'seeding', 'completed', 'cancelled', 'error')
+                THEN status ELSE 'queued' END,
+            COALESCE(priority, 0),
+            download_limit,
+            upload_limit,
+            files,
+            seeding_policy,
+            COALESCE(extra_trackers, 1),
+            error,
+            COALESCE(attempts, 0),
+            retry_at
+        FROM downloads;
+
+        DROP TABLE downloads;
+        ALTER TABLE downloads_new RENAME TO downloads;",
+    )
+}
+
+/// Migration 4: rebuild the library table with an integer primary key.
+/// The ids are kept from the rowids.
+fn add_library_keys(tx: &Transaction) -> Result<(), RusqliteError> {
+    tx.execute_batch(
+        "CREATE TABLE library_new (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            game TEXT NOT NULL,
+            path TEXT NOT NULL,
+            executable TEXT
+        );
+
+        INSERT INTO library_new (id, name, game, path, executable)
+        SELECT rowid, COALESCE(name, ''), COALESCE(game, name, ''), COALESCE(path, ''), executable
+        FROM library;
+
+        DROP TABLE library;
+        ALTER TABLE library_new RENAME TO library;",
+    )
+}
+
+/// Migration 5: add the names of the files of the torrents, they are needed to delete or move the files without the metadata.
+fn add_download_file_names(tx: &Transaction) -> Result<(), RusqliteError> {
+    add_column(tx, "downloads", "file_names", "TEXT")
+}
+
+/// Migration 6: link the library entries to the downloads they come from, a download adds at most one entry.
+fn add_library_download_id(tx: &Transaction) -> Result<(), RusqliteError> {
+    add_column(tx, "library", "download_id", "INTEGER")?;
+    tx.execute("CREATE UNIQUE INDEX IF NOT EXISTS library_download_id ON library (download_id)", [])?;
+
+    Ok(())
+}
+
+/// Migration 7: add the uploaded bytes and the seeding time of the downloads, so the seeding policy is resumed after a restart.
+fn add_download_seeding(tx: &Transaction) -> Result<(), RusqliteError> {
+    add_column(tx, "downloads", "uploaded_bytes", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column(tx, "downloads", "seeding_seconds", "INTEGER NOT NULL DEFAULT 0")?;
+
+    Ok(())
+}
+
+/// Migration 8: put in error the unfinished downloads whose link is the web page of the torrent.
+/// The first versions of the application stored the 1337x page instead of the magnet link, it can not be added to the session.
+/// They are not retried, the user adds the download again to resolve its magnet link.
+fn park_web_links(tx: &Transaction) -> Result<(), RusqliteError> {
+    tx.execute(
+        "UPDATE downloads
+            SET status = 'error',
+                error = 'The link of the download is a web page, add the download again to get its magnet link',
+                retry_at = NULL
+            WHERE (link LIKE 'http://%' OR link LIKE 'https://%')
+                AND status IN ('queued', 'downloading', 'paused', 'seeding', 'error')",
+        [],
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Function to create an empty fixture database on disk, in the temporary directory.
+    fn fixture(name: &str) -> (PathBuf, Connection) {
+        let path = std::env::temp_dir().join(format!("gameio-migrations-{}.sqlite", name));
+        let _ = std::fs::remove_file(&path);
+        let conn = Connection::open(&path).unwrap();
+
+        (path, conn)
+    }
+
+    /// Function to get the columns of a table.
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?1)").unwrap();
+        let columns = stmt
+            .query_map([table], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<Vec<String>, RusqliteError>>()
+            .unwrap();
+        columns
+    }
+
+    #[test]
+    fn test_create_tables() {
+        let (path, mut conn) = fixture("create-tables");
+
+        assert_eq!(migrate_to(&mut conn, 1).unwrap(), 1);
+        assert_eq!(columns(&conn, "downloads"), vec!["name", "game", "link", "uploader", "path"]);
+        assert_eq!(columns(&conn, "library"), vec!["name", "game", "path", "executable"]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_add_download_columns() {
+        let (path, mut conn) = fixture("add-download-columns");
+
+        // Database written by the first versions of the application, without a schema version
+        let tx = conn.transaction().unwrap();
+        create_tables(&tx).unwrap();
+        tx.commit().unwrap();
+        conn.execute(
+            "INSERT INTO downloads (name, game, link, uploader, path) VALUES ('Zelda', 'Zelda', 'magnet:?xt=urn:btih:...', 'Noidea', 'path')",
+            [],
+        )
+        .unwrap();
+        drop(conn);
+
+        let mut conn = Connection::open(&path).unwrap();
+        assert_eq!(migrate(&mut conn).unwrap(), latest_version());
+
+        let (name, status, priority, extra_trackers): (String, String, i64, bool) = conn
+            .query_row("SELECT name, status, priority, extra_trackers FROM downloads", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap();
+        assert_eq!(name, "Zelda");
+        assert_eq!(status, "queued");
+        assert_eq!(priority, 1);
+        assert!(extra_trackers);
+        assert!(columns(&conn, "downloads").contains(&"retry_at".to_string()));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_add_download_keys() {
+        let (path, mut conn) = fixture("add-download-keys");
+
+        // Database at version 2, with a cancelled and a new download of the same torrent
+        migrate_to(&mut conn, 2).unwrap();
+        conn.execute_batch(
+            "INSERT INTO downloads (name, game, link, uploader, path, status, info_hash) VALUES ('Zelda', 'Zelda', 'magnet:?xt=urn:btih:...', 'Noidea', 'path', 'cancelled', 'hash');
+            INSERT INTO downloads (name, game, link, uploader, path, status, info_hash) VALUES ('Zelda', 'Zelda', 'magnet:?xt=urn:btih:...', 'Noidea', 'path', 'downloading', 'hash');
+            INSERT INTO downloads (name, game, link, uploader, path, status) VALUES ('Mario', 'Mario', 'magnet:?xt=urn:btih:...', 'Noidea', 'path', 'unknown');",
+        )
+        .unwrap();
+        drop(conn);
+
+        let mut conn = Connection::open(&path).unwrap();
+        assert_eq!(migrate_to(&mut conn, 3).unwrap(), 3);
+
+        let rows: Vec<(i64, Option<String>, String)> = conn
+            .prepare("SELECT id, info_hash, status FROM downloads ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, RusqliteError>>()
+            .unwrap();
+        assert_eq!(rows, vec![
+            (1, None, "cancelled".to_string()),
+            (2, Some("hash".to_string()), "downloading".to_string()),
+            (3, None, "queued".to_string()),
+        ]);
+
+        // The info hash is now unique and the status is checked
+        assert!(conn.execute("UPDATE downloads SET info_hash = 'hash' WHERE id = 1", []).is_err());
+        assert!(conn.execute("UPDATE downloads SET status = 'unknown' WHERE id = 1", []).is_err());
+
+        let created_at: i64 = conn.query_row("SELECT created_at FROM downloads WHERE id = 1", [], |row| row.get(0)).unwrap();
+        assert!(created_at > 0);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_add_library_keys() {
+        let (path, mut conn) = fixture("add-library-keys");
+
+        // Database at version 3, with a game added by hand without its game column
+        migrate_to(&mut conn, 3).unwrap();
+        conn.execute("INSERT INTO library (name, path, executable) VALUES ('Zelda', 'path', 'path/zelda.exe')", []).unwrap();
+        drop(conn);
+
+        let mut conn = Connection::open(&path).unwrap();
+        assert_eq!(migrate_to(&mut conn, 4).unwrap(), 4);
+
+        let row: (i64, String, String, Option<String>) = conn
+            .query_row("SELECT id, name, game, executable FROM library", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap();
+        assert_eq!(row, (1, "Zelda".to_string(), "Zelda".to_string(), Some("path/zelda.exe".to_string())));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_add_download_file_names() {
+        let (path, mut conn) = fixture("add-download-file-names");
+
+        migrate_to(&mut conn, 4).unwrap();
+        assert!(!columns(&conn, "downloads").contains(&"file_names".to_string()));
+
+        assert_eq!(migrate_to(&mut conn, 5).unwrap(), 5);
+        assert!(columns(&conn, "downloads").contains(&"file_names".to_string()));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_add_library_download_id() {
+        let (path, mut conn) = fixture("add-library-download-id");
+
+        migrate_to(&mut conn, 5).unwrap();
+        conn.execute("INSERT INTO library (name, game, path) VALUES ('Zelda', 'Zelda', 'path')", []).unwrap();
+
+        assert_eq!(migrate_to(&mut conn, 6).unwrap(), 6);
+        assert!(columns(&conn, "library").contains(&"download_id".to_string()));
+
+        // The games added by hand have no download, the others come from a single download
+        conn.execute("INSERT INTO library (name, game, path) VALUES ('Mario', 'Mario', 'path')", []).unwrap();
+        conn.execute("INSERT INTO library (name, game, path, download_id) VALUES ('Zelda', 'Zelda', 'path', 1)", []).unwrap();
+        assert!(conn.execute("INSERT INTO library (name, game, path, download_id) VALUES ('Zelda', 'Zelda', 'path', 1)", []).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_add_download_seeding() {
+        let (path, mut conn) = fixture("add-download-seeding");
+
+        migrate_to(&mut conn, 6).unwrap();
+        conn.execute("INSERT INTO downloads (name, game, link, uploader, path, status) VALUES ('Zelda', 'Zelda', 'magnet:?xt=urn:btih:...', 'Noidea', 'path', 'seeding')", []).unwrap();
+
+        assert_eq!(migrate_to(&mut conn, 7).unwrap(), 7);
+
+        // The downloads seeding before the migration start their quota from zero
+        let seeding: (u64, u64) = conn
+            .query_row("SELECT uploaded_bytes, seeding_seconds FROM downloads", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!(seeding, (0, 0));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_park_web_links() {
+        let (path, mut conn) = fixture("park-web-links");
+
+        // Database at version 7, with a download added from the 1337x page by the first versions
+        migrate_to(&mut conn, 7).unwrap();
+        conn.execute_batch(
+            "INSERT INTO downloads (name, game, link, uploader, path, status) VALUES ('Zelda', 'Zelda', 'https://1337x.to/torrent/1/zelda/', 'Noidea', 'path', 'queued');
+            INSERT INTO downloads (name, game, link, uploader, path, status) VALUES ('Mario', 'Mario', 'magnet:?xt=urn:btih:...', 'Noidea', 'path', 'queued');
+            INSERT INTO downloads (name, game, link, uploader, path, status) VALUES ('Kirby', 'Kirby', 'https://1337x.to/torrent/2/kirby/', 'Noidea', 'path', 'completed');",
+        )
+        .unwrap();
+
+        assert_eq!(migrate_to(&mut conn, 8).unwrap(), 8);
+
+        let rows: Vec<(String, String, Option<i64>)> = conn
+            .prepare("SELECT name, status, retry_at FROM downloads ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, RusqliteError>>()
+            .unwrap();
+        assert_eq!(rows, vec![
+            ("Zelda".to_string(), "error".to_string(), None),
+            ("Mario".to_string(), "queued".to_string(), None),
+            ("Kirby".to_string(), "completed".to_string(), None),
+        ]);
+
+        let error: Option<String> = conn.query_row("SELECT error FROM downloads WHERE name = 'Zelda'", [], |row| row.get(0)).unwrap();
+        assert!(error.unwrap().contains("add the download again"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_is_idempotent() {
+        let (path, mut conn) = fixture("idempotent");
+
+        assert_eq!(migrate(&mut conn).unwrap(), latest_version());
+        assert_eq!(migrate(&mut conn).unwrap(), latest_version());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_concurrently() {
+        let (path, conn) = fixture("concurrent");
+        drop(conn);
+
+        // Every connection opens the same database at the same time, only one of them applies each migration
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let mut conn = Connection::open(&path).unwrap();
+                    conn.busy_timeout(std::time::Duration::from_secs(5)).unwrap();
+                    migrate(&mut conn).unwrap()
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), latest_version());
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
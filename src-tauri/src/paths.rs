@@ -0,0 +1,84 @@
+/*
+This file will be used to resolve the directory of each download from the path template.
+The template can use the {download_path}, {game} and {torrent_name} placeholders,
+the game and torrent names are sanitized so that they are valid directory names on every filesystem.
+*/
+
+// Default template, every game gets its own directory with one subdirectory per torrent
+pub const DEFAULT_PATH_TEMPLATE: &str = "{download_path}/{game}/{torrent_name}";
+
+// Characters that are not allowed in a file name on Windows, '/' is also the separator on Unix
+const ILLEGAL_CHARACTERS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+// File names reserved by Windows, with or without an extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Function to resolve the directory of a download from the path template.
+///
+/// # Arguments
+/// * `template` - The path template, e.g. {download_path}/{game}/{torrent_name}.
+/// * `download_path` - The download path from the environment file, it is not sanitized.
+/// * `game` - The name of the game of the download.
+/// * `torrent_name` - The name of the torrent.
+///
+/// # Returns
+/// The directory where the torrent will be downloaded.
+///
+pub fn resolve_download_path(template: &str, download_path: &str, game: &str, torrent_name: &str) -> String {
+    template
+        .replace("{game}", &sanitize_component(game))
+        .replace("{torrent_name}", &sanitize_component(torrent_name))
+        .replace("{download_path}", download_path.trim_end_matches(['/', '\\']))
+}
+
+/// Function to turn a name into a valid directory name.
+/// Illegal and control characters are replaced with '_', trailing dots and spaces are removed
+/// and the names reserved by Windows are prefixed with '_'.
+///
+/// # Arguments
+/// * `name` - The name to sanitize.
+///
+pub fn sanitize_component(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| if ILLEGAL_CHARACTERS.contains(&c) || c.is_control() { '_' } else { c })
+        .collect();
+    let sanitized = sanitized.trim().trim_end_matches(['.', ' ']).to_string();
+
+    // Names made only of dots, or empty names, would point to another directory
+    if sanitized.is_empty() {
+        return "_".to_string();
+    }
+
+    let stem = sanitized.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(stem)) {
+        return format!("_{}", sanitized);
+    }
+
+    sanitized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_download_path() {
+        let path = resolve_download_path(DEFAULT_PATH_TEMPLATE, "/home/user/Downloads/", "Half-Life: Alyx", "Half-Life.Alyx-FitGirl");
+
+        assert_eq!(path, "/home/user/Downloads/Half-Life_ Alyx/Half-Life.Alyx-FitGirl");
+    }
+
+    #[test]
+    fn test_sanitize_component() {
+        assert_eq!(sanitize_component("What? <Game> \"1/2\" *|\\"), "What_ _Game_ _1_2_ ___");
+        assert_eq!(sanitize_component("Game..."), "Game");
+        assert_eq!(sanitize_component(".."), "_");
+        assert_eq!(sanitize_component("con.txt"), "_con.txt");
+        assert_eq!(sanitize_component("Console"), "Console");
+    }
+}
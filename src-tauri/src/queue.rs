@@ -0,0 +1,320 @@
+/*
+This file will be used to manage the download queue.
+Only MAX_ACTIVE_DOWNLOADS downloads run at the same time, the others wait in the downloads table with the queued status.
+The queue is processed by a background task every time it is notified or every QUEUE_INTERVAL.
+*/
+
+use once_cell::sync::Lazy;
+use std::time::Duration;
+use tokio::sync::Notify;
+use log::{trace, debug, error};
+
+use crate::database::{Database, Download, DownloadStatus};
+use crate::env;
+use crate::library;
+use crate::magnet::Magnet;
+use crate::scheduler;
+use crate::torrent::{BandwidthLimits, DownloadOptions, SeedingPolicy, TorrentManager};
+
+// Interval between two checks of the queue when nobody notifies it
+const QUEUE_INTERVAL: Duration = Duration::from_secs(30);
+
+// Number of attempts to start a download before giving up, the user can still retry it
+const MAX_ATTEMPTS: u32 = 5;
+
+// Delay before the first retry of a failed download, it doubles after each attempt
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+// Longest delay between two retries
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+// Define the global notifier, it will be used to wake up the queue when a slot may have changed
+static QUEUE: Lazy<Notify> = Lazy::new(Notify::new);
+
+/// Function to wake up the queue.
+/// It will be called every time a download is added, paused, resumed, cancelled, completed or moved.
+pub fn notify() {
+    QUEUE.notify_one();
+}
+
+/// Function to run the queue for the whole life of the application.
+/// It will be spawned as a background task next to the torrent manager.
+///
+/// # Arguments
+/// * `manager` - The torrent manager used to start the downloads.
+/// * `db` - The database of the downloads.
+///
+pub async fn run(manager: TorrentManager, db: Database) {
+    loop {
+        if let Err(e) = process_queue(&manager, &db).await {
+            error!("error processing the download queue: {:?}", e);
+        }
+
+        // Wait until the queue is notified or the interval elapses
+        let _ = tokio::time::timeout(QUEUE_INTERVAL, QUEUE.notified()).await;
+    }
+}
+
+/// Function to compute the delay before the next retry of a failed download.
+///
+/// # Arguments
+/// * `attempts` - The number of failed attempts, including the last one.
+///
+fn retry_delay(attempts: u32) -> Duration {
+    RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Function to record the error of a download that failed to start.
+/// The download is retried after an exponential backoff until MAX_ATTEMPTS is reached.
+/// A download that has been paused or cancelled meanwhile keeps its status.
+///
+/// # Arguments
+/// * `db` - The database of the downloads.
+/// * `id` - The id of the download.
+/// * `attempts` - The number of failed attempts, including this one.
+/// * `error` - The error that prevented the download from starting.
+///
+async fn record_error(db: &Database, id: i64, attempts: u32, error: &anyhow::Error) {
+    let retry_at = if attempts < MAX_ATTEMPTS {
+        let delay = retry_delay(attempts);
+        debug!("Retrying download {} in {:?}", id, delay);
+        Some(chrono::Utc::now().timestamp() + delay.as_secs() as i64)
+    } else {
+        debug!("Giving up on download {} after {} attempts", id, attempts);
+        None
+    };
+
+    if let Err(e) = db.set_download_error(id, &error.to_string(), retry_at).await {
+        error!("error updating download {}: {:?}", id, e);
+    }
+}
+
+/// Function to start the queued downloads while there are free slots.
+/// Flow:
+/// * `Requeue the failed downloads whose retry time has come
+/// * `Get the downloads ordered by priority
+/// * `Count the active downloads
+/// * `Start the first queued downloads until all the slots are taken
+///
+/// # Arguments
+/// * `manager` - The torrent manager used to start the downloads.
+/// * `db` - The database of the downloads.
+///
+async fn process_queue(manager: &TorrentManager, db: &Database) -> Result<(), anyhow::Error> {
+    trace!("Processing download queue");
+
+    // Put back in the queue the failed downloads that can be retried
+    let requeued = db.requeue_failed_downloads(chrono::Utc::now().timestamp()).await?;
+    if requeued > 0 {
+        debug!("Retrying {} failed downloads", requeued);
+    }
+
+    // Do not start anything outside of the scheduled windows
+    if scheduler::is_paused() {
+        debug!("Downloads paused by the scheduler");
+        return Ok(());
+    }
+
+    let max_active = env::get_max_active_downloads().await;
+    let downloads = db.get_downloads().await?;
+
+    // Count the active downloads
+    let mut active = downloads
+        .iter()
+        .filter(|download| download.status == DownloadStatus::Downloading)
+        .count();
+    debug!("Active downloads: {}/{}", active, max_active);
+
+    // Start the queued downloads in order of priority
+    for download in downloads.iter().filter(|download| download.status == DownloadStatus::Queued) {
+        if active >= max_active {
+            break;
+        }
+
+        start_download(manager, db, download).await?;
+        active += 1;
+    }
+
+    Ok(())
+}
+
+/// Function to start a queued download.
+/// If the torrent is already in the session it is resumed, otherwise it is added to the session.
+///
+/// # Arguments
+/// * `manager` - The torrent manager used to start the download.
+/// * `db` - The database of the downloads.
+/// * `download` - The row of the download in the downloads table.
+///
+async fn start_download(manager: &TorrentManager, db: &Database, download: &Download) -> Result<(), anyhow::Error> {
+    let id = download.id;
+    trace!("Starting download {}", id);
+
+    db.set_download_status(id, DownloadStatus::Downloading).await?;
+
+    // Resume the torrent if it was paused in the session
+    if manager.has_torrent(id) {
+        return manager.resume(id).await;
+    }
+
+    spawn_download(manager, db, download);
+
+    Ok(())
+}
+
+/// Function to add the extra trackers from the configuration to a magnet link.
+/// Links that are not magnet links, like imported .torrent files, are returned as they are.
+///
+/// # Arguments
+/// * `link` - The link of the download.
+///
+pub async fn add_extra_trackers(link: &str) -> String {
+    let mut magnet = match Magnet::parse(link) {
+        Ok(magnet) => magnet,
+        Err(_) => return link.to_string(),
+    };
+
+    let trackers = env::get_extra_trackers().await;
+    debug!("Adding {} extra trackers", trackers.len());
+    magnet.add_trackers(&trackers);
+
+    magnet.to_link()
+}
+
+/// Function to add a seeding download back to the session.
+/// It will be called at the beginning of the application, the download seeds until its seeding policy is reached
+/// and it does not take a slot. It is already in the library, so it is not added again.
+///
+/// # Arguments
+/// * `manager` - The torrent manager used to add the download.
+/// * `db` - The database of the downloads.
+/// * `download` - The row of the download in the downloads table.
+///
+pub fn restore_seeding(manager: &TorrentManager, db: &Database, download: &Download) {
+    trace!("Restoring seeding download {}", download.id);
+    spawn_download(manager, db, download);
+}
+
+/// Function to download a torrent as a new task.
+/// The torrent is added paused and started only if the download is still meant to run once it is in the session.
+/// If the torrent can not start, or fails while it downloads, the error is recorded and the download is retried later.
+/// Once the torrent is downloaded the game is added to the library, its slot is freed and it seeds
+/// following its seeding policy, the download is marked as completed when it stops seeding.
+/// A download that was already seeding only seeds, its game is already in the library.
+///
+/// # Arguments
+/// * `manager` - The torrent manager used to add the download.
+/// * `db` - The database of the downloads.
+/// * `download` - The row of the download in the downloads table.
+///
+fn spawn_download(manager: &TorrentManager, db: &Database, download: &Download) {
+    let id = download.id;
+    let seeding = download.status == DownloadStatus::Seeding;
+    let name = download.name.clone();
+    let game = download.game.clone();
+    let download_path = download.path.clone();
+    let link = download.link.clone();
+    let options = DownloadOptions {
+        limits: BandwidthLimits {
+            download: download.download_limit,
+            upload: download.upload_limit,
+        },
+        only_files: download.files.clone(),
+    };
+    let seeding_policy: Option<SeedingPolicy> = download
+        .seeding_policy
+        .as_deref()
+        .and_then(|policy| policy.parse().ok());
+    let extra_trackers = download.extra_trackers;
+    let attempts = download.attempts + 1;
+
+    // Until the torrent is in the session, a pause or a cancel of the download is kept for when it is added
+    manager.reserve(id);
+
+    let manager = manager.clone();
+    let db = db.clone();
+    tauri::async_runtime::spawn(async move {
+
+        // Add the extra trackers to the magnet link
+        let link = if extra_trackers {
+            add_extra_trackers(&link).await
+        } else {
+            link
+        };
+
+        // Record the error if the torrent can not start or fails, e.g. when it does not fit on the disk
+        if let Err(e) = manager.download_torrent(&db, id, &download_path, &link, options).await {
+            error!("error starting download {}: {}", id, e);
+            record_error(&db, id, attempts, &e).await;
+            notify();
+            return;
+        }
+
+        // The download worked, forget its previous errors
+        if let Err(e) = db.clear_download_error(id).await {
+            error!("error updating download {}: {:?}", id, e);
+        }
+
+        if manager.is_finished(id) {
+
+            // Add the game to the library, or find the entry added before the download was restored
+            let library_id = if seeding {
+                db.find_library_entry_by_download(id).await.map_err(anyhow::Error::from)
+            } else {
+                library::add_completed_download(&db, id, &name, &game, &download_path).await.map(Some)
+            };
+            let library_id = match library_id {
+                Ok(library_id) => library_id,
+                Err(e) => {
+                    error!("error adding download {} to the library: {:?}", id, e);
+                    None
+                }
+            };
+
+            // Free the slot of the download while it is seeding
+            if !seeding {
+                if let Err(e) = db.set_download_status(id, DownloadStatus::Seeding).await {
+                    error!("error updating download {}: {:?}", id, e);
+                }
+                notify();
+            }
+
+            // Seed until the policy of the download, or the global one, is reached
+            let seeding_policy = match seeding_policy {
+                Some(policy) => policy,
+                None => env::get_seeding_policy().await,
+            };
+            if let Err(e) = manager.seed(&db, id, seeding_policy).await {
+                error!("error seeding download {}: {:?}", id, e);
+            }
+
+            // Mark the download as completed and move it to the library unless it has been cancelled
+            if manager.has_torrent(id) {
+                if let Err(e) = db.set_download_status(id, DownloadStatus::Completed).await {
+                    error!("error updating download {}: {:?}", id, e);
+                }
+                if let Some(library_id) = library_id {
+                    if let Err(e) = library::move_to_library(&manager, &db, id, library_id, &name, &game, &download_path).await {
+                        error!("error moving download {} to the library: {:?}", id, e);
+                    }
+                }
+            }
+        }
+        notify();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), Duration::from_secs(30));
+        assert_eq!(retry_delay(2), Duration::from_secs(60));
+        assert_eq!(retry_delay(4), Duration::from_secs(240));
+        assert_eq!(retry_delay(100), MAX_RETRY_DELAY);
+    }
+}
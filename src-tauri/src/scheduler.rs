@@ -0,0 +1,375 @@
+/*
+This file will be used to schedule the downloads in weekly time windows.
+Outside of the configured windows the active torrents are paused and the queue does not start new ones,
+inside a window the torrents are resumed with the speed limit of the window.
+The schedule is stored in the SCHEDULE variable of the .env file, e.g. `mon-fri@22:00-07:00;sat,sun@00:00-24:00/1048576/0`.
+*/
+
+use chrono::{Datelike, Local, Timelike};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+use log::{trace, debug, error};
+use tauri::Manager;
+
+use crate::database::{Database, DownloadStatus};
+use crate::env;
+use crate::queue;
+use crate::torrent::{BandwidthLimits, TorrentManager};
+
+// Interval between two checks of the schedule
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(30);
+
+// Names of the days of the week, starting from Monday
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+// Number of minutes in a day, it is the end of a window that lasts until midnight
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+// Define the global mode of the scheduler, this will be used by the queue and reported to the frontend
+static MODE: Lazy<Mutex<SchedulerMode>> = Lazy::new(|| Mutex::new(SchedulerMode::Always));
+
+/// Weekly time window in which the downloads are allowed to run.
+/// Days go from 0 (Monday) to 6 (Sunday), start and end are minutes since midnight.
+/// A window whose end is not after its start ends on the next day.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleWindow {
+    pub days: Vec<u32>,
+    pub start: u32,
+    pub end: u32,
+    pub limits: Option<BandwidthLimits>,
+}
+
+impl ScheduleWindow {
+
+    /// Function to check if the window contains a moment of the week.
+    ///
+    /// # Arguments
+    /// * `weekday` - The day of the week, from 0 (Monday) to 6 (Sunday).
+    /// * `minute` - The minutes since midnight.
+    ///
+    pub fn contains(&self, weekday: u32, minute: u32) -> bool {
+        if self.start < self.end {
+            return self.days.contains(&weekday) && minute >= self.start && minute < self.end;
+        }
+
+        // The window starts on one of its days and ends on the next day
+        let yesterday = (weekday + 6) % 7;
+        (self.days.contains(&weekday) && minute >= self.start)
+            || (self.days.contains(&yesterday) && minute < self.end)
+    }
+}
+
+/// The window is written as `days@HH:MM-HH:MM`, optionally followed by `/download_limit/upload_limit` in bytes per second.
+/// Days are separated by commas and can be ranges, e.g. `mon-fri` or `sat,sun`.
+impl fmt::Display for ScheduleWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // A day out of the week is written as a number, so it can not be read back
+        let days: Vec<String> = self
+            .days
+            .iter()
+            .map(|day| DAYS.get(*day as usize).map_or_else(|| day.to_string(), |name| name.to_string()))
+            .collect();
+        write!(
+            f,
+            "{}@{:02}:{:02}-{:02}:{:02}",
+            days.join(","),
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )?;
+
+        if let Some(limits) = &self.limits {
+            write!(f, "/{}/{}", limits.download.unwrap_or(0), limits.upload.unwrap_or(0))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for ScheduleWindow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (days, rest) = s
+            .trim()
+            .split_once('@')
+            .ok_or_else(|| anyhow::anyhow!("missing '@' in window: {}", s))?;
+
+        // Read the optional speed limits
+        let mut parts = rest.split('/');
+        let hours = parts.next().unwrap_or_default();
+        let limits = match (parts.next(), parts.next()) {
+            (Some(download), Some(upload)) => Some(BandwidthLimits {
+                download: Some(download.parse()?),
+                upload: Some(upload.parse()?),
+            }),
+            (None, None) => None,
+            _ => anyhow::bail!("invalid limits in window: {}", s),
+        };
+
+        // Read the hours
+        let (start, end) = hours
+            .split_once('-')
+            .ok_or_else(|| anyhow::anyhow!("invalid hours in window: {}", s))?;
+
+        Ok(Self {
+            days: parse_days(days)?,
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+            limits,
+        })
+    }
+}
+
+/// Current mode of the scheduler.
+/// * `Always` - no schedule is configured, the downloads always run
+/// * `InWindow` - the downloads run with the limits of the current window
+/// * `OutsideWindow` - the downloads are paused until the next window
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum SchedulerMode {
+    Always,
+    InWindow { limits: Option<BandwidthLimits> },
+    OutsideWindow,
+}
+
+/// Function to compute the mode of the scheduler for a moment of the week.
+///
+/// # Arguments
+/// * `windows` - The configured windows.
+/// * `weekday` - The day of the week, from 0 (Monday) to 6 (Sunday).
+/// * `minute` - The minutes since midnight.
+///
+pub fn mode_at(windows: &[ScheduleWindow], weekday: u32, minute: u32) -> SchedulerMode {
+    if windows.is_empty() {
+        return SchedulerMode::Always;
+    }
+
+    match windows.iter().find(|window| window.contains(weekday, minute)) {
+        Some(window) => SchedulerMode::InWindow { limits: window.limits },
+        None => SchedulerMode::OutsideWindow,
+    }
+}
+
+/// Function to read a schedule, the windows are separated by ';'.
+///
+/// # Arguments
+/// * `text` - The schedule as stored in the .env file.
+///
+pub fn parse_schedule(text: &str) -> Result<Vec<ScheduleWindow>, anyhow::Error> {
+    text.split(';')
+        .filter(|window| !window.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// Function to write a schedule as stored in the .env file.
+///
+/// # Arguments
+/// * `windows` - The windows of the schedule.
+///
+pub fn format_schedule(windows: &[ScheduleWindow]) -> String {
+    windows
+        .iter()
+        .map(|window| window.to_string())
+        .collect::<Vec<String>>()
+        .join(";")
+}
+
+/// Function to get the current mode of the scheduler.
+/// It will be used by the queue and by the settings page.
+pub fn get_mode() -> SchedulerMode {
+    match MODE.lock() {
+        Ok(mode) => mode.clone(),
+        Err(_) => SchedulerMode::Always,
+    }
+}
+
+/// Function to check if the downloads are paused by the scheduler.
+pub fn is_paused() -> bool {
+    get_mode() == SchedulerMode::OutsideWindow
+}
+
+/// Function to run the scheduler for the whole life of the application.
+/// It will be spawned as a background task next to the torrent manager,
+/// every time the mode changes it is applied to the torrents and sent to the frontend with the scheduler-mode event.
+///
+/// # Arguments
+/// * `manager` - The torrent manager used to pause and resume the torrents.
+/// * `db` - The database of the downloads.
+/// * `app_handle` - The handle of the Tauri application used to emit the events.
+///
+pub async fn run(manager: TorrentManager, db: Database, app_handle: tauri::AppHandle) {
+    let mut interval = tokio::time::interval(SCHEDULER_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        // Compute the mode for the current local time
+        let now = Local::now();
+        let windows = env::get_schedule().await;
+        let mode = mode_at(&windows, now.weekday().num_days_from_monday(), now.hour() * 60 + now.minute());
+
+        if mode == get_mode() {
+            continue;
+        }
+        debug!("Scheduler mode: {:?}", mode);
+
+        if let Err(e) = apply_mode(&manager, &db, &mode).await {
+            error!("error applying scheduler mode: {:?}", e);
+        }
+        if let Ok(mut current) = MODE.lock() {
+            *current = mode.clone();
+        }
+        if let Err(e) = app_handle.emit_all("scheduler-mode", &mode) {
+            error!("error emitting scheduler mode: {:?}", e);
+        }
+
+        // Let the queue start the downloads that were waiting for a window
+        queue::notify();
+    }
+}
+
+/// Function to apply a mode of the scheduler to the torrents.
+/// Only the downloads that are running are paused or resumed, the ones paused by the user are left as they are.
+///
+/// # Arguments
+/// * `manager` - The torrent manager used to pause and resume the torrents.
+/// * `db` - The database of the downloads.
+/// * `mode` - The new mode of the scheduler.
+///
+async fn apply_mode(manager: &TorrentManager, db: &Database, mode: &SchedulerMode) -> Result<(), anyhow::Error> {
+    trace!("Applying scheduler mode");
+
+    // Apply the limits of the window, or the global ones
+    let limits = match mode {
+        SchedulerMode::InWindow { limits: Some(limits) } => *limits,
+        _ => BandwidthLimits {
+            download: Some(env::get_download_limit().await),
+            upload: Some(env::get_upload_limit().await),
+        },
+    };
+    manager.set_global_limits(limits);
+
+    // Pause or resume the running torrents
+    for download in db.get_downloads().await? {
+        let id = download.id;
+        let running = matches!(download.status, DownloadStatus::Downloading | DownloadStatus::Seeding);
+        if !running || !manager.has_torrent(id) {
+            continue;
+        }
+
+        let result = match mode {
+            SchedulerMode::OutsideWindow => manager.pause(id).await,
+            _ => manager.resume(id).await,
+        };
+        if let Err(e) = result {
+            debug!("Scheduler could not update torrent {}: {:?}", id, e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Function to read the days of a window, e.g. `mon-fri` or `sat,sun`.
+fn parse_days(text: &str) -> Result<Vec<u32>, anyhow::Error> {
+    let day = |name: &str| {
+        DAYS.iter()
+            .position(|day| day.eq_ignore_ascii_case(name.trim()))
+            .map(|day| day as u32)
+            .ok_or_else(|| anyhow::anyhow!("invalid day: {}", name))
+    };
+
+    let mut days = Vec::new();
+    for part in text.split(',') {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (day(first)?, day(last)?),
+            None => (day(part)?, day(part)?),
+        };
+
+        // Ranges can wrap around the end of the week, e.g. fri-mon
+        let mut current = first;
+        loop {
+            if !days.contains(&current) {
+                days.push(current);
+            }
+            if current == last {
+                break;
+            }
+            current = (current + 1) % 7;
+        }
+    }
+
+    Ok(days)
+}
+
+/// Function to read a time of the day as minutes since midnight, `24:00` is allowed as the end of a day.
+fn parse_time(text: &str) -> Result<u32, anyhow::Error> {
+    let (hours, minutes) = text
+        .trim()
+        .split_once(':')
+        .ok_or_else(|| anyhow::anyhow!("invalid time: {}", text))?;
+    let time = hours.parse::<u32>()? * 60 + minutes.parse::<u32>()?;
+
+    if minutes.parse::<u32>()? >= 60 || time > MINUTES_PER_DAY {
+        anyhow::bail!("invalid time: {}", text);
+    }
+
+    Ok(time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_window() {
+        let window: ScheduleWindow = "mon-fri@22:00-07:00".parse().unwrap();
+
+        assert_eq!(window.days, vec![0, 1, 2, 3, 4]);
+        assert_eq!(window.start, 22 * 60);
+        assert_eq!(window.end, 7 * 60);
+        assert_eq!(window.limits, None);
+    }
+
+    #[test]
+    fn test_parse_schedule() {
+        let windows = parse_schedule("fri-mon@00:00-24:00/1048576/0;wed@12:30-13:00").unwrap();
+
+        assert_eq!(windows.len(), 2);
+        assert_eq!(windows[0].days, vec![4, 5, 6, 0]);
+        assert_eq!(windows[0].limits.unwrap().download, Some(1048576));
+        assert_eq!(parse_schedule(&format_schedule(&windows)).unwrap(), windows);
+        assert!(parse_schedule("mon@25:00-26:00").is_err());
+        assert!(parse_schedule("funday@00:00-01:00").is_err());
+
+        // A day out of the week is not taken for another day
+        let window = ScheduleWindow { days: vec![9], start: 0, end: 60, limits: None };
+        assert!(parse_schedule(&format_schedule(&[window])).is_err());
+    }
+
+    #[test]
+    fn test_window_contains() {
+        let night: ScheduleWindow = "mon-fri@22:00-07:00".parse().unwrap();
+
+        assert!(night.contains(0, 23 * 60));
+        assert!(night.contains(1, 6 * 60));
+        assert!(night.contains(5, 6 * 60)); // Saturday morning, after Friday night
+        assert!(!night.contains(0, 6 * 60)); // Monday morning, Sunday is not in the window
+        assert!(!night.contains(2, 12 * 60));
+    }
+
+    #[test]
+    fn test_mode_at() {
+        let windows = parse_schedule("sat,sun@00:00-24:00/1024/0").unwrap();
+
+        assert_eq!(mode_at(&[], 0, 0), SchedulerMode::Always);
+        assert_eq!(mode_at(&windows, 0, 0), SchedulerMode::OutsideWindow);
+        assert!(matches!(mode_at(&windows, 6, 600), SchedulerMode::InWindow { limits: Some(_) }));
+    }
+}
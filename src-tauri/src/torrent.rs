@@ -1,142 +1,1389 @@
 /*
 This file will be used to manage the torrents.
 It will allow to add torrents, to get the status of all torrents and to start the torrents at the beginning of the program.
+All the torrents share a single librqbit Session owned by the TorrentManager.
 */
 
 use librqbit::ManagedTorrent;
-use librqbit::{AddTorrent, AddTorrentOptions, AddTorrentResponse, Session};
+use librqbit::{torrent_from_bytes, AddTorrent, AddTorrentOptions, AddTorrentResponse, Session, SessionOptions, TorrentStatsState};
+use librqbit::limits::LimitsConfig;
 use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::num::NonZeroU32;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use log::{trace, debug, error};
+use tauri::Manager;
 
-// Define the global list of handles, this will be used to store all the active torrents
-static HANDLES: Lazy<Arc<Mutex<Vec<Arc<ManagedTorrent>>>>> =
-    Lazy::new(|| Arc::new(Mutex::new(Vec::new())));
+use crate::database::{Database, Download, DownloadStatus};
+use crate::scheduler;
 
-/// Function to download a torrent from a magnet link.
-/// It will be recalled when the user clicks on the torrent to download from the TODO page.
-/// Flow:
-/// * `Create a new session
-/// * `Add the torrent to the session\
-/// * `Add the handle to the HANDLES list
-/// * `Wait for the handle to finish
-/// * `Delete thje session
-/// 
+// Interval between two download-progress events sent to the frontend
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+// Interval between two saves of the progress of the downloads in the database
+const SAVE_PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+// Longest time spent fetching the metadata of a torrent that is not in the session
+const METADATA_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Interval between two checks of the state of a downloading torrent
+const COMPLETION_INTERVAL: Duration = Duration::from_secs(1);
+
+// Interval between two checks of the seeding policy of a torrent
+const SEEDING_INTERVAL: Duration = Duration::from_secs(10);
+
+// Directory where the imported .torrent files are kept, they are needed to restart the downloads
+const TORRENTS_DIR: &str = "torrents";
+
+// Define the global map of the bytes fetched from each peer, this will be used to compute the speed of the peers
+static PEER_SAMPLES: Lazy<Arc<Mutex<HashMap<(i64, String), (u64, Instant)>>>> =
+    Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+// Define the global map of handles, this will be used to store all the active torrents by their download id
+static HANDLES: Lazy<Arc<Mutex<HashMap<i64, Arc<ManagedTorrent>>>>> =
+    Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+// Define the global map of the torrents being added to the session, a torrent leaves it for the HANDLES map once it is added
+// The value is None while the torrent is starting and Some(delete_files) once it has been cancelled meanwhile
+static PENDING: Lazy<Arc<Mutex<HashMap<i64, Option<bool>>>>> =
+    Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+// Define the global map of seeding torrents, this will be used to know how much each torrent has seeded
+static SEEDING: Lazy<Arc<Mutex<HashMap<i64, Seeding>>>> =
+    Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+/// Seeding of a torrent, it adds the uploaded bytes and the seeding time of the previous runs to the current one.
+/// The uploaded bytes of the session restart from zero when the torrent is added again.
+#[derive(Debug, Clone, Copy)]
+struct Seeding {
+    started: Instant,
+    uploaded_bytes: u64,
+    seconds: u64,
+}
+
+impl Seeding {
+
+    /// Function to get the bytes uploaded by a torrent, including the previous runs.
+    ///
+    /// # Arguments
+    /// * `handle` - The handle of the torrent in the session.
+    ///
+    fn uploaded_bytes(&self, handle: &ManagedTorrent) -> u64 {
+        self.uploaded_bytes + handle.stats().uploaded_bytes
+    }
+
+    /// Function to get the time a torrent has been seeding for, including the previous runs.
+    fn elapsed(&self) -> Duration {
+        Duration::from_secs(self.seconds) + self.started.elapsed()
+    }
+}
+
+/// Function to get the handle of an active torrent from the HANDLES map.
+///
 /// # Arguments
-/// * `directory` - A string slice that holds the directory where the torrent will be downloaded.
-/// * `magnet_link` - A string slice that holds the magnet link of the torrent.
+/// * `id` - The id of the download in the downloads table.
 ///
-pub async fn download_torrent(directory: &str, magnet_link: &str) {
+fn get_handle(id: i64) -> Result<Arc<ManagedTorrent>, anyhow::Error> {
+    let handles = HANDLES
+        .lock()
+        .map_err(|e| anyhow::anyhow!("error locking global handles: {:?}", e))?;
 
-    // Create the session
-    trace!("Creating session");
-    debug!("Directory: {}", directory);
-    let session = match Session::new(directory.into()).await {
-        Ok(s) => s,
-        Err(e) => {
-            error!("error creating session: {:?}", e);
-            return;
+    handles
+        .get(&id)
+        .cloned()
+        .ok_or_else(|| anyhow::anyhow!("no active torrent for download {}", id))
+}
+
+/// Function to get the files of a torrent of the session from the metadata of its handle.
+///
+/// # Arguments
+/// * `handle` - The handle of the torrent in the session.
+///
+fn handle_files(handle: &ManagedTorrent) -> Result<Vec<TorrentFile>, anyhow::Error> {
+    let mut files = Vec::new();
+    for (index, (name, size)) in handle.info().info.iter_filenames_and_lengths()?.enumerate() {
+        files.push(TorrentFile {
+            index,
+            name: name.to_string()?,
+            size,
+        });
+    }
+
+    Ok(files)
+}
+
+/// Function to check if a torrent is being added to the session.
+///
+/// # Arguments
+/// * `id` - The id of the download in the downloads table.
+///
+fn is_pending(id: i64) -> bool {
+    PENDING
+        .lock()
+        .map(|pending| pending.contains_key(&id))
+        .unwrap_or(false)
+}
+
+/// Function to move a torrent that has been added to the session from the PENDING map to the HANDLES map.
+/// Both maps are locked together, so a cancel sees the torrent in one of them.
+///
+/// # Arguments
+/// * `id` - The id of the download in the downloads table.
+/// * `handle` - The handle of the torrent in the session.
+///
+/// # Returns
+/// Some(delete_files) if the torrent has been cancelled while it was added, it is not moved then.
+///
+fn activate(id: i64, handle: &Arc<ManagedTorrent>) -> Result<Option<bool>, anyhow::Error> {
+    let mut pending = PENDING
+        .lock()
+        .map_err(|e| anyhow::anyhow!("error locking global pending starts: {:?}", e))?;
+
+    let cancelled = pending.remove(&id).flatten();
+    if cancelled.is_none() {
+        HANDLES
+            .lock()
+            .map_err(|e| anyhow::anyhow!("error locking global handles: {:?}", e))?
+            .insert(id, handle.clone());
+    }
+
+    Ok(cancelled)
+}
+
+/// Bandwidth limits of the session or of a single download.
+/// Limits are in bytes per second, None or 0 means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct BandwidthLimits {
+    pub download: Option<u32>,
+    pub upload: Option<u32>,
+}
+
+impl From<BandwidthLimits> for LimitsConfig {
+    fn from(limits: BandwidthLimits) -> Self {
+        LimitsConfig {
+            download_bps: limits.download.and_then(NonZeroU32::new),
+            upload_bps: limits.upload.and_then(NonZeroU32::new),
+        }
+    }
+}
+
+/// Seeding policy of the session or of a single download.
+/// It decides when a completed torrent stops seeding:
+/// * `Never` - the torrent stops as soon as it is completed
+/// * `Ratio` - the torrent stops once it has uploaded ratio times its size
+/// * `Duration` - the torrent stops after seeding for the given number of seconds
+/// * `Forever` - the torrent seeds until the user pauses or cancels it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", content = "value", rename_all = "snake_case")]
+pub enum SeedingPolicy {
+    Never,
+    Ratio(f64),
+    Duration(u64),
+    Forever,
+}
+
+impl Default for SeedingPolicy {
+    fn default() -> Self {
+        SeedingPolicy::Ratio(1.0)
+    }
+}
+
+impl SeedingPolicy {
+
+    /// Function to check if a seeding torrent should stop.
+    ///
+    /// # Arguments
+    /// * `ratio` - The current share ratio of the torrent.
+    /// * `elapsed` - The time the torrent has been seeding for.
+    ///
+    pub fn is_reached(&self, ratio: f64, elapsed: Duration) -> bool {
+        match self {
+            SeedingPolicy::Never => true,
+            SeedingPolicy::Ratio(limit) => ratio >= *limit,
+            SeedingPolicy::Duration(limit) => elapsed.as_secs() >= *limit,
+            SeedingPolicy::Forever => false,
+        }
+    }
+}
+
+/// The policy is stored as text in the .env file and in the downloads table,
+/// e.g. `never`, `ratio:1.5`, `duration:3600` or `forever`.
+impl fmt::Display for SeedingPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SeedingPolicy::Never => write!(f, "never"),
+            SeedingPolicy::Ratio(ratio) => write!(f, "ratio:{}", ratio),
+            SeedingPolicy::Duration(seconds) => write!(f, "duration:{}", seconds),
+            SeedingPolicy::Forever => write!(f, "forever"),
+        }
+    }
+}
+
+impl FromStr for SeedingPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("ratio", ratio)) => Ok(SeedingPolicy::Ratio(ratio.parse()?)),
+            Some(("duration", seconds)) => Ok(SeedingPolicy::Duration(seconds.parse()?)),
+            None if s == "never" => Ok(SeedingPolicy::Never),
+            None if s == "forever" => Ok(SeedingPolicy::Forever),
+            _ => anyhow::bail!("invalid seeding policy: {}", s),
         }
+    }
+}
+
+/// Function to compute the share ratio of a torrent.
+/// It is the number of uploaded bytes over the number of downloaded bytes.
+///
+/// # Arguments
+/// * `handle` - The handle of the torrent in the session.
+/// * `uploaded_bytes` - The bytes uploaded by the torrent.
+///
+fn share_ratio(handle: &ManagedTorrent, uploaded_bytes: u64) -> f64 {
+    let stats = handle.stats();
+    if stats.progress_bytes == 0 {
+        return 0.0;
+    }
+
+    uploaded_bytes as f64 / stats.progress_bytes as f64
+}
+
+/// Metadata read from a .torrent file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TorrentFileInfo {
+    pub info_hash: String,
+    pub name: String,
+    pub trackers: Vec<String>,
+}
+
+/// Function to read the info hash, the name and the trackers of a torrent from the content of a .torrent file.
+///
+/// # Arguments
+/// * `bytes` - The content of the .torrent file.
+///
+pub fn read_torrent_file(bytes: &[u8]) -> Result<TorrentFileInfo, anyhow::Error> {
+    let torrent = torrent_from_bytes(bytes)?;
+
+    let info_hash = torrent.info_hash.as_string();
+    let name = match &torrent.info.name {
+        Some(name) => String::from_utf8_lossy(name.as_ref()).into_owned(),
+        None => info_hash.clone(),
     };
-    trace!("Session created");
-
-    // Add the torrent to the session
-    trace!("Adding torrent");
-    trace!("Magnet link: {}", magnet_link);
-    let handle = match session
-        .add_torrent(
-            AddTorrent::from_url(magnet_link),
+
+    // Read the trackers from the announce and announce-list keys
+    let mut trackers: Vec<String> = Vec::new();
+    for tracker in torrent.announce.iter().chain(torrent.announce_list.iter().flatten()) {
+        let tracker = String::from_utf8_lossy(tracker.as_ref()).into_owned();
+        if !trackers.contains(&tracker) {
+            trackers.push(tracker);
+        }
+    }
+
+    Ok(TorrentFileInfo { info_hash, name, trackers })
+}
+
+/// Function to keep a copy of an imported .torrent file.
+/// The file is saved in the TORRENTS_DIR directory and named after its info hash.
+///
+/// # Arguments
+/// * `bytes` - The content of the .torrent file.
+/// * `info_hash` - The info hash of the torrent.
+///
+/// # Returns
+/// The path of the saved file, it is stored as the link of the download.
+///
+pub fn save_torrent_file(bytes: &[u8], info_hash: &str) -> Result<String, anyhow::Error> {
+    std::fs::create_dir_all(TORRENTS_DIR)?;
+
+    let path = std::path::Path::new(TORRENTS_DIR).join(format!("{}.torrent", info_hash));
+    std::fs::write(&path, bytes)?;
+
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Function to delete the copy of an imported .torrent file.
+/// Links that are not in the TORRENTS_DIR directory, like magnet links, are ignored.
+///
+/// # Arguments
+/// * `link` - The link of the download.
+///
+pub fn remove_torrent_file(link: &str) -> Result<(), anyhow::Error> {
+    let path = Path::new(link);
+    if path.parent() != Some(Path::new(TORRENTS_DIR)) {
+        return Ok(());
+    }
+
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Error returned when the files of a torrent do not fit on the disk.
+/// Sizes are in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InsufficientSpace {
+    pub required: u64,
+    pub available: u64,
+}
+
+impl fmt::Display for InsufficientSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "not enough disk space: {} bytes required, {} bytes available",
+            self.required, self.available
+        )
+    }
+}
+
+impl std::error::Error for InsufficientSpace {}
+
+/// Function to compute the space still needed on disk by the files of a torrent.
+/// The bytes already downloaded, e.g. in a previous run, are not counted again.
+/// The length of the files on disk is not used, librqbit allocates them before they are downloaded.
+///
+/// # Arguments
+/// * `files` - The files of the torrent.
+/// * `only_files` - The indices of the files to download, None to download all of them.
+/// * `downloaded` - The bytes of the selected files already downloaded.
+///
+fn required_space(files: &[TorrentFile], only_files: Option<&[usize]>, downloaded: u64) -> u64 {
+    files
+        .iter()
+        .filter(|file| only_files.is_none_or(|only_files| only_files.contains(&file.index)))
+        .map(|file| file.size)
+        .sum::<u64>()
+        .saturating_sub(downloaded)
+}
+
+/// Function to remove the directories left empty after a file has been deleted or moved.
+/// The directories are removed up to the download directory, which is kept.
+///
+/// # Arguments
+/// * `path` - The path of the deleted or moved file.
+/// * `directory` - The download directory of the file.
+///
+fn remove_empty_parents(path: &Path, directory: &Path) {
+    for parent in path.ancestors().skip(1).take_while(|parent| *parent != directory) {
+        // remove_dir fails on the directories that are not empty
+        if std::fs::remove_dir(parent).is_err() {
+            break;
+        }
+    }
+}
+
+/// Function to get the source of a torrent from the link of a download.
+/// The link is either a magnet link or the path of a local .torrent file.
+///
+/// # Arguments
+/// * `link` - The link of the download.
+///
+fn torrent_source(link: &str) -> Result<AddTorrent<'_>, anyhow::Error> {
+    if link.starts_with("magnet:") {
+        Ok(AddTorrent::from_url(link))
+    } else {
+        AddTorrent::from_local_filename(link)
+    }
+}
+
+/// Network options of the session.
+/// They are read from the environment file when the session is created, a change applies on the next start.
+/// The listen port is the first free port between listen_port_start and listen_port_end (included).
+/// There is no IPv6 option, librqbit 7 only listens and runs the DHT on IPv4.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkOptions {
+    pub listen_port_start: u16,
+    pub listen_port_end: u16,
+    pub dht: bool,
+}
+
+impl Default for NetworkOptions {
+    fn default() -> Self {
+        Self {
+            listen_port_start: 4240,
+            listen_port_end: 4260,
+            dht: true,
+        }
+    }
+}
+
+impl NetworkOptions {
+
+    /// Function to get the listen port range in the form expected by the session.
+    /// It will return None if the range is empty, starts at port 0 or ends at port 65535,
+    /// the end of the range is excluded by the session so 65535 can not be included.
+    pub fn listen_port_range(&self) -> Option<Range<u16>> {
+        if self.listen_port_start == 0 || self.listen_port_start > self.listen_port_end || self.listen_port_end == u16::MAX {
+            return None;
+        }
+
+        Some(self.listen_port_start..self.listen_port_end + 1)
+    }
+}
+
+/// Network state of the session.
+/// It is shown on the settings page next to the configured options.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkStatus {
+    pub options: NetworkOptions,
+    pub active_port: Option<u16>,
+    pub dht_running: bool,
+}
+
+/// Options of a single download.
+/// They are read from the downloads table every time the torrent is added to the session.
+#[derive(Debug, Clone, Default)]
+pub struct DownloadOptions {
+    pub limits: BandwidthLimits,
+    pub only_files: Option<Vec<usize>>,
+}
+
+/// File contained in a torrent.
+/// The index is the one expected by the only_files option of librqbit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TorrentFile {
+    pub index: usize,
+    pub name: String,
+    pub size: u64,
+}
+
+/// Shared torrent engine.
+/// It owns the single librqbit Session that every download is attached to.
+/// It is created once at the beginning of the application and stored in the Tauri managed state.
+#[derive(Clone)]
+pub struct TorrentManager {
+    session: Arc<Session>,
+}
+
+impl TorrentManager {
+
+    /// Function to create the torrent manager and its session.
+    /// It will be called once at the beginning of the application.
+    ///
+    /// # Arguments
+    /// * `directory` - A string slice that holds the default directory of the session.
+    /// * `limits` - The global bandwidth limits of the session.
+    /// * `network` - The listen port range and the DHT toggle of the session.
+    ///
+    /// The session falls back to not listening for incoming peers when the port range is invalid or no port of it is free.
+    ///
+    pub async fn new(directory: &str, limits: BandwidthLimits, network: NetworkOptions) -> Result<Self, anyhow::Error> {
+        let session_options = |listen_port_range: Option<Range<u16>>| SessionOptions {
+            ratelimits: limits.into(),
+            listen_port_range,
+            disable_dht: !network.dht,
+            ..Default::default()
+        };
+
+        // Create the session
+        trace!("Creating session");
+        debug!("Directory: {}", directory);
+        debug!("Global limits: {:?}", limits);
+        debug!("Network options: {:?}", network);
+        let listen_port_range = network.listen_port_range();
+        let listening = listen_port_range.is_some();
+        if !listening {
+            error!("invalid listen port range {}-{}, incoming peers are disabled", network.listen_port_start, network.listen_port_end);
+        }
+        let session = match Session::new_with_opts(directory.into(), session_options(listen_port_range)).await {
+            Ok(session) => session,
+            Err(e) if listening => {
+                error!("error listening on ports {}-{}: {:?}, incoming peers are disabled", network.listen_port_start, network.listen_port_end, e);
+                Session::new_with_opts(directory.into(), session_options(None)).await?
+            }
+            Err(e) => return Err(e),
+        };
+        trace!("Session created");
+
+        Ok(Self { session })
+    }
+
+    /// Function to get the list of files of a torrent without downloading it.
+    /// It will be called before starting a download to let the user choose the files.
+    ///
+    /// # Arguments
+    /// * `link` - A string slice that holds the magnet link or the .torrent file path of the torrent.
+    ///
+    /// # Returns
+    /// The files of the torrent with their index and size in bytes.
+    ///
+    /// # Errors
+    /// The metadata of a magnet link is fetched from the peers, it fails after METADATA_TIMEOUT.
+    ///
+    pub async fn list_files(&self, link: &str) -> Result<Vec<TorrentFile>, anyhow::Error> {
+
+        // Resolve the metadata of the torrent without adding it to the session
+        trace!("Resolving metadata");
+        trace!("Link: {}", link);
+        let add = self.session.add_torrent(
+            torrent_source(link)?,
             Some(AddTorrentOptions {
-                // Allow writing on top of existing files.
-                overwrite: true,
+                list_only: true,
                 ..Default::default()
             }),
-        )
-        .await
-    {
-        Ok(response) => match response {
-            AddTorrentResponse::Added(_, handle) => handle,
-            _ => unreachable!(),
-        },
-        Err(e) => {
-            error!("error adding torrent: {:?}", e);
-            return;
+        );
+        let response = tokio::time::timeout(METADATA_TIMEOUT, add)
+            .await
+            .map_err(|_| anyhow::anyhow!("timed out fetching the metadata of the torrent"))??;
+        let info = match response {
+            AddTorrentResponse::ListOnly(list) => list.info,
+            _ => anyhow::bail!("torrent is already in the session"),
+        };
+        trace!("Metadata resolved");
+
+        // Build the list of files
+        let mut files = Vec::new();
+        for (index, (name, size)) in info.iter_filenames_and_lengths()?.enumerate() {
+            files.push(TorrentFile {
+                index,
+                name: name.to_string()?,
+                size,
+            });
         }
-    };
-    trace!("Torrent added");
+        debug!("Number of files: {}", files.len());
 
-    // Add the handle to the global list
-    trace!("Adding handle to global list");
-    let handle_clone: Arc<ManagedTorrent> = handle.clone();
-    let mut handles = match HANDLES.lock() {
-        Ok(h) => h,
-        Err(e) => {
-            error!("error locking global handles: {:?}", e);
-            return;
+        Ok(files)
+    }
+
+    /// Function to check that the files of a torrent of the session fit on the disk before starting it.
+    /// The files are read from the metadata of the handle, so nothing is fetched again.
+    /// It waits for librqbit to initialize the torrent, so the bytes already downloaded are known.
+    ///
+    /// # Arguments
+    /// * `id` - The id of the download in the downloads table.
+    /// * `handle` - The handle of the torrent in the session.
+    /// * `directory` - A string slice that holds the directory where the torrent will be downloaded.
+    /// * `only_files` - The indices of the files to download, None to download all of them.
+    ///
+    /// # Errors
+    /// * `InsufficientSpace` - with the required and available bytes, if the files do not fit.
+    ///
+    async fn check_disk_space(&self, id: i64, handle: &ManagedTorrent, directory: &str, only_files: Option<&[usize]>) -> Result<(), anyhow::Error> {
+        // librqbit checks the files already on disk while the torrent is initializing, even if it is paused
+        let stats = loop {
+            let stats = handle.stats();
+            match stats.state {
+                TorrentStatsState::Initializing if self.has_torrent(id) => tokio::time::sleep(COMPLETION_INTERVAL).await,
+                TorrentStatsState::Error => return Err(anyhow::anyhow!("torrent error: {}", stats.error.unwrap_or_default())),
+                _ => break stats,
+            }
+        };
+
+        let files = handle_files(handle)?;
+        let required = required_space(&files, only_files, stats.progress_bytes);
+
+        // The directory must exist to know the filesystem it is on
+        std::fs::create_dir_all(directory)?;
+        let available = fs2::available_space(directory)?;
+        debug!("Disk space: {} bytes required, {} bytes available", required, available);
+
+        if required > available {
+            return Err(InsufficientSpace { required, available }.into());
         }
-    };
-    handles.push(handle_clone);
-    trace!("Handle added to global list");
 
-    // Wait for the handle to finish
-    trace!("Waiting for torrent to complete");
-    if let Err(e) = handle.wait_until_completed().await {
-        error!("error waiting for torrent to complete: {:?}", e);
+        Ok(())
+    }
+
+    /// Function to register a torrent that is about to be added to the session.
+    /// Until the torrent is added, has_torrent is true and a pause, resume or cancel is applied once it is in the session.
+    /// It will be called before the task that downloads the torrent is spawned.
+    ///
+    /// # Arguments
+    /// * `id` - The id of the download in the downloads table.
+    ///
+    pub fn reserve(&self, id: i64) {
+        match PENDING.lock() {
+            Ok(mut pending) => {
+                pending.entry(id).or_insert(None);
+            }
+            Err(e) => error!("error locking global pending starts: {:?}", e),
+        }
+    }
+
+    /// Function to download a torrent from a magnet link or a local .torrent file.
+    /// It will be recalled when the user clicks on the torrent to download from the TODO page.
+    /// Flow:
+    /// * `Add the torrent to the shared session, paused
+    /// * `Add the handle to the HANDLES map, or delete the torrent if it was cancelled meanwhile
+    /// * `Store the info hash of the torrent in the database
+    /// * `Start the torrent if the download is still meant to run and its files fit on the disk
+    /// * `Wait for the handle to finish
+    /// 
+    /// # Arguments
+    /// * `db` - The database where the info hash is stored.
+    /// * `id` - The id of the download in the downloads table.
+    /// * `directory` - A string slice that holds the directory where the torrent will be downloaded.
+    /// * `link` - A string slice that holds the magnet link or the .torrent file path of the torrent.
+    /// * `options` - The bandwidth limits and selected files of the download.
+    ///
+    /// # Errors
+    /// Any error that prevents the torrent from starting, e.g. `InsufficientSpace`,
+    /// or that stops it once it is running, the torrent is removed from the session then, keeping its files.
+    /// A torrent that is cancelled is not an error.
+    ///
+    pub async fn download_torrent(&self, db: &Database, id: i64, directory: &str, link: &str, options: DownloadOptions) -> Result<(), anyhow::Error> {
+        self.reserve(id);
+        let only_files = options.only_files.clone();
+
+        // Add the torrent to the session, or give up the start
+        let handle = match self.add_paused(directory, link, options).await {
+            Ok(handle) => handle,
+            Err(e) => {
+                if let Ok(mut pending) = PENDING.lock() {
+                    pending.remove(&id);
+                }
+                return Err(e);
+            }
+        };
+
+        // Add the handle to the global map, unless the download has been cancelled while the torrent was added
+        trace!("Adding handle to global map");
+        if let Some(delete_files) = activate(id, &handle)? {
+            debug!("Download {} cancelled while starting", id);
+            self.session.delete(handle.id().into(), delete_files).await?;
+            return Ok(());
+        }
+        trace!("Handle added to global map");
+
+        // Store the info hash, it identifies the torrent across restarts
+        let info_hash = handle.info_hash().as_string();
+        debug!("Info hash: {}", info_hash);
+        if let Err(e) = db.set_download_info_hash(id, &info_hash).await {
+            error!("error storing info hash: {:?}", e);
+        }
+
+        // Store the names of the files, they are needed to delete or move the files once the torrent leaves the session
+        match handle_files(&handle) {
+            Ok(files) => {
+                let file_names: Vec<String> = files.into_iter().map(|file| file.name).collect();
+                if let Err(e) = db.set_download_file_names(id, &file_names).await {
+                    error!("error storing file names: {:?}", e);
+                }
+            }
+            Err(e) => error!("error reading file names: {:?}", e),
+        }
+
+        // Start the torrent, the download may have been paused while the torrent was added
+        if let Err(e) = self.apply_status(db, id, &handle, directory, only_files.as_deref()).await {
+            error!("error starting torrent: {:?}", e);
+            return Err(e);
+        }
+        if !self.has_torrent(id) {
+            return Ok(());
+        }
+
+        // Wait for the handle to finish
+        trace!("Waiting for torrent to complete");
+        loop {
+
+            // Stop waiting if the torrent has been cancelled
+            if !self.has_torrent(id) {
+                trace!("Torrent {} cancelled", id);
+                return Ok(());
+            }
+
+            let stats = handle.stats();
+            if stats.finished {
+                break;
+            }
+
+            // Remove the failed torrent from the session, it is added again when the download is retried
+            if let TorrentStatsState::Error = stats.state {
+                let e = anyhow::anyhow!("torrent error: {}", stats.error.unwrap_or_default());
+                error!("error downloading torrent {}: {:?}", id, e);
+                self.cancel(id, false).await?;
+                return Err(e);
+            }
+
+            tokio::time::sleep(COMPLETION_INTERVAL).await;
+        }
+        trace!("Torrent completed");
+
+        Ok(())
+    }
+
+    /// Function to add a torrent to the session, paused.
+    ///
+    /// # Arguments
+    /// * `directory` - A string slice that holds the directory where the torrent will be downloaded.
+    /// * `link` - A string slice that holds the magnet link or the .torrent file path of the torrent.
+    /// * `options` - The bandwidth limits and selected files of the download.
+    ///
+    async fn add_paused(&self, directory: &str, link: &str, options: DownloadOptions) -> Result<Arc<ManagedTorrent>, anyhow::Error> {
+
+        // Read the source of the torrent
+        trace!("Adding torrent");
+        trace!("Link: {}", link);
+        let source = match torrent_source(link) {
+            Ok(source) => source,
+            Err(e) => {
+                error!("error reading torrent: {:?}", e);
+                return Err(e);
+            }
+        };
+
+        // Add the torrent to the session, it is started once the status of the download is checked
+        let handle = match self.session
+            .add_torrent(
+                source,
+                Some(AddTorrentOptions {
+                    // Allow writing on top of existing files.
+                    overwrite: true,
+                    output_folder: Some(directory.to_string()),
+                    ratelimits: options.limits.into(),
+                    only_files: options.only_files,
+                    paused: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+        {
+            Ok(response) => match response {
+                AddTorrentResponse::Added(_, handle) => handle,
+                AddTorrentResponse::AlreadyManaged(_, handle) => handle,
+                _ => unreachable!(),
+            },
+            Err(e) => {
+                error!("error adding torrent: {:?}", e);
+                return Err(e);
+            }
+        };
+        trace!("Torrent added");
+
+        Ok(handle)
+    }
+
+    /// Function to start or keep paused a torrent that has just been added, following the status of its download.
+    /// The torrent runs if the download is downloading or seeding and the scheduler does not pause the downloads.
+    /// The status is read again once the torrent is started, so a pause received meanwhile is not lost.
+    ///
+    /// # Arguments
+    /// * `db` - The database of the downloads.
+    /// * `id` - The id of the download in the downloads table.
+    /// * `handle` - The handle of the torrent in the session.
+    /// * `directory` - The directory where the torrent is downloaded.
+    /// * `only_files` - The indices of the files to download, None to download all of them.
+    ///
+    /// # Errors
+    /// * `InsufficientSpace` - if the files do not fit on the disk, the torrent is removed from the session.
+    ///
+    async fn apply_status(&self, db: &Database, id: i64, handle: &Arc<ManagedTorrent>, directory: &str, only_files: Option<&[usize]>) -> Result<(), anyhow::Error> {
+        let status = db.get_download(id).await?.map(|download| download.status);
+        match status {
+            // The download has been removed or cancelled without going through the cancel of the torrent
+            None | Some(DownloadStatus::Cancelled) => return self.cancel(id, false).await,
+            Some(DownloadStatus::Downloading | DownloadStatus::Seeding) if !scheduler::is_paused() => {}
+            _ => {
+                debug!("Torrent {} stays paused", id);
+                return Ok(());
+            }
+        }
+
+        // Check that the files fit on the disk, the torrent does not stay in the session otherwise
+        trace!("Checking disk space");
+        if let Err(e) = self.check_disk_space(id, handle, directory, only_files).await {
+            error!("error checking disk space: {:?}", e);
+            self.cancel(id, false).await?;
+            return Err(e);
+        }
+
+        trace!("Starting torrent {}", id);
+        if let Err(e) = self.session.unpause(handle).await {
+            // The torrent has been cancelled meanwhile
+            if !self.has_torrent(id) {
+                return Ok(());
+            }
+            return Err(e);
+        }
+
+        // Pause the torrent again if the download has been paused while it was started
+        let status = db.get_download(id).await?.map(|download| download.status);
+        if !matches!(status, Some(DownloadStatus::Downloading | DownloadStatus::Seeding)) || scheduler::is_paused() {
+            debug!("Download {} paused while starting", id);
+            self.session.pause(handle).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Function to seed a completed torrent until its seeding policy is reached.
+    /// The torrent is paused once the policy is reached, it returns early if the torrent is cancelled.
+    /// The uploaded bytes and the seeding time are saved in the database, so the policy is resumed after a restart.
+    ///
+    /// # Arguments
+    /// * `db` - The database of the downloads.
+    /// * `id` - The id of the download in the downloads table.
+    /// * `policy` - The seeding policy of the download.
+    ///
+    pub async fn seed(&self, db: &Database, id: i64, policy: SeedingPolicy) -> Result<(), anyhow::Error> {
+        trace!("Seeding torrent {} with policy {}", id, policy);
+        let handle = get_handle(id)?;
+
+        // Resume from the uploaded bytes and the seeding time of the previous runs
+        let seeding = match db.get_download(id).await? {
+            Some(download) => Seeding { started: Instant::now(), uploaded_bytes: download.uploaded_bytes, seconds: download.seeding_seconds },
+            None => Seeding { started: Instant::now(), uploaded_bytes: 0, seconds: 0 },
+        };
+
+        // Register the torrent as seeding
+        SEEDING
+            .lock()
+            .map_err(|e| anyhow::anyhow!("error locking global seeding: {:?}", e))?
+            .insert(id, seeding);
+
+        let result = loop {
+
+            // Save how much the torrent has seeded
+            let uploaded_bytes = seeding.uploaded_bytes(&handle);
+            let elapsed = seeding.elapsed();
+            if let Err(e) = db.set_download_seeding(id, uploaded_bytes, elapsed.as_secs()).await {
+                error!("error saving seeding of download {}: {:?}", id, e);
+            }
+
+            // Stop seeding if the torrent has been cancelled
+            if !self.has_torrent(id) {
+                break Ok(());
+            }
+
+            // Pause the torrent once the policy is reached
+            if policy.is_reached(share_ratio(&handle, uploaded_bytes), elapsed) {
+                break self.session.pause(&handle).await;
+            }
+
+            tokio::time::sleep(SEEDING_INTERVAL).await;
+        };
+
+        // Unregister the torrent
+        if let Ok(mut seeding) = SEEDING.lock() {
+            seeding.remove(&id);
+        }
+        trace!("Torrent {} stopped seeding", id);
+
+        result
+    }
+
+    /// Function to check if a download has a torrent in the session, or being added to it.
+    ///
+    /// # Arguments
+    /// * `id` - The id of the download in the downloads table.
+    ///
+    pub fn has_torrent(&self, id: i64) -> bool {
+        get_handle(id).is_ok() || is_pending(id)
+    }
+
+    /// Function to check if the torrent of a download has finished downloading.
+    ///
+    /// # Arguments
+    /// * `id` - The id of the download in the downloads table.
+    ///
+    pub fn is_finished(&self, id: i64) -> bool {
+        get_handle(id)
+            .map(|handle| handle.stats().finished)
+            .unwrap_or(false)
+    }
+
+    /// Function to pause an active torrent.
+    /// It will be called when the user clicks on the pause button of a download.
+    ///
+    /// # Arguments
+    /// * `id` - The id of the download in the downloads table.
+    ///
+    pub async fn pause(&self, id: i64) -> Result<(), anyhow::Error> {
+        trace!("Pausing torrent {}", id);
+
+        // A torrent being added stays paused if its download is not meant to run
+        if is_pending(id) {
+            debug!("Torrent {} is starting, its status is applied once it is added", id);
+            return Ok(());
+        }
+
+        let handle = get_handle(id)?;
+        self.session.pause(&handle).await?;
+        trace!("Torrent {} paused", id);
+
+        Ok(())
     }
-    trace!("Torrent completed");
 
-    // Delete the session implicitly by dropping it
-    drop(session);
+    /// Function to resume a paused torrent.
+    /// It will be called when the user clicks on the resume button of a download.
+    ///
+    /// # Arguments
+    /// * `id` - The id of the download in the downloads table.
+    ///
+    pub async fn resume(&self, id: i64) -> Result<(), anyhow::Error> {
+        trace!("Resuming torrent {}", id);
+
+        // A torrent being added is started if its download is meant to run
+        if is_pending(id) {
+            debug!("Torrent {} is starting, its status is applied once it is added", id);
+            return Ok(());
+        }
+
+        let handle = get_handle(id)?;
+        self.session.unpause(&handle).await?;
+        trace!("Torrent {} resumed", id);
+
+        Ok(())
+    }
+
+    /// Function to cancel a torrent.
+    /// The torrent is removed from the session and from the HANDLES map.
+    /// A torrent that is being added is removed as soon as it is in the session.
+    ///
+    /// # Arguments
+    /// * `id` - The id of the download in the downloads table.
+    /// * `delete_files` - Whether the downloaded files should be deleted from disk.
+    ///
+    pub async fn cancel(&self, id: i64, delete_files: bool) -> Result<(), anyhow::Error> {
+        trace!("Cancelling torrent {}", id);
+
+        // The PENDING map stays locked until the handle is read, so the torrent can not be moved to HANDLES in between
+        let handle = {
+            let mut pending = PENDING
+                .lock()
+                .map_err(|e| anyhow::anyhow!("error locking global pending starts: {:?}", e))?;
+            if let Some(cancelled) = pending.get_mut(&id) {
+                debug!("Torrent {} is starting, it is removed once it is added", id);
+                *cancelled = Some(delete_files);
+                return Ok(());
+            }
+            get_handle(id)?
+        };
+        self.session.delete(handle.id().into(), delete_files).await?;
+
+        // Remove the handle from the global map
+        HANDLES
+            .lock()
+            .map_err(|e| anyhow::anyhow!("error locking global handles: {:?}", e))?
+            .remove(&id);
+        if let Ok(mut samples) = PEER_SAMPLES.lock() {
+            samples.retain(|(torrent, _), _| *torrent != id);
+        }
+        trace!("Torrent {} cancelled", id);
+
+        Ok(())
+    }
+
+    /// Function to get the names of the files of a torrent of the session.
+    /// It will be called before the torrent leaves the session, to move its files afterwards.
+    ///
+    /// # Arguments
+    /// * `id` - The id of the download in the downloads table.
+    ///
+    pub fn file_names(&self, id: i64) -> Result<Vec<String>, anyhow::Error> {
+        let handle = get_handle(id)?;
+        let files = handle_files(&handle)?;
+
+        Ok(files.into_iter().map(|file| file.name).collect())
+    }
+
+    /// Function to get the names of the files of a download whose torrent is not in the session.
+    /// The names stored when the torrent was added are used, the metadata is only fetched for the downloads added before they were stored.
+    ///
+    /// # Arguments
+    /// * `download` - The row of the download in the downloads table.
+    ///
+    pub async fn stored_file_names(&self, download: &Download) -> Result<Vec<String>, anyhow::Error> {
+        if let Some(file_names) = &download.file_names {
+            return Ok(file_names.clone());
+        }
+
+        let files = self.list_files(&download.link).await?;
+        Ok(files.into_iter().map(|file| file.name).collect())
+    }
+
+    /// Function to delete the files of a torrent that is not in the session.
+    /// Only the files of the torrent are deleted, then the directories they leave empty.
+    /// The download directory itself is kept, it may be shared with other downloads.
+    ///
+    /// # Arguments
+    /// * `directory` - The directory where the torrent was downloaded.
+    /// * `file_names` - The paths of the files of the torrent, relative to the directory.
+    ///
+    pub fn delete_files(&self, directory: &str, file_names: &[String]) -> Result<(), anyhow::Error> {
+        trace!("Deleting {} files from {}", file_names.len(), directory);
+        let directory = Path::new(directory);
+
+        for name in file_names {
+            let path = directory.join(name);
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => debug!("Deleted {}", path.display()),
+            }
+            remove_empty_parents(&path, directory);
+        }
+
+        Ok(())
+    }
+
+    /// Function to move the files of a torrent that is not in the session to another directory.
+    /// The files keep their path relative to the directory, the files that were not downloaded are skipped.
+    /// Files are renamed when possible and copied when the directories are on different filesystems.
+    ///
+    /// # Arguments
+    /// * `from` - The directory where the torrent was downloaded.
+    /// * `to` - The directory where the files are moved.
+    /// * `file_names` - The paths of the files of the torrent, relative to the directories.
+    ///
+    pub fn move_files(&self, from: &str, to: &str, file_names: &[String]) -> Result<(), anyhow::Error> {
+        trace!("Moving {} files from {} to {}", file_names.len(), from, to);
+        let from = Path::new(from);
+        let to = Path::new(to);
+
+        for name in file_names {
+            let source = from.join(name);
+            if !source.exists() {
+                continue;
+            }
+
+            let destination = to.join(name);
+            if let Some(parent) = destination.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            if std::fs::rename(&source, &destination).is_err() {
+                std::fs::copy(&source, &destination)?;
+                std::fs::remove_file(&source)?;
+            }
+            debug!("Moved {} to {}", source.display(), destination.display());
+            remove_empty_parents(&source, from);
+        }
+
+        Ok(())
+    }
+
+    /// Function to inspect the peers, the trackers and the DHT of a torrent.
+    /// Flow:
+    /// * `Get the live peers of the torrent
+    /// * `Compute the speed of each peer from the bytes fetched since the previous inspection
+    /// * `Report the trackers the torrent was added with and the state of the DHT
+    ///
+    /// # Arguments
+    /// * `id` - The id of the download in the downloads table.
+    ///
+    pub fn inspect(&self, id: i64) -> Result<DownloadInspection, anyhow::Error> {
+        trace!("Inspecting torrent {}", id);
+        let handle = get_handle(id)?;
+        let now = Instant::now();
+
+        let mut samples = PEER_SAMPLES
+            .lock()
+            .map_err(|e| anyhow::anyhow!("error locking global peer samples: {:?}", e))?;
+
+        // Forget the samples of the peers of this torrent, they are replaced by the new ones
+        let previous: HashMap<String, (u64, Instant)> = samples
+            .iter()
+            .filter(|((torrent, _), _)| *torrent == id)
+            .map(|((_, address), sample)| (address.clone(), *sample))
+            .collect();
+        samples.retain(|(torrent, _), _| *torrent != id);
+
+        // Get the live peers, a paused torrent has none
+        let mut peers = Vec::new();
+        if let Some(live) = handle.live() {
+            for (address, stats) in live.per_peer_stats_snapshot(Default::default()).peers {
+                let fetched_bytes = stats.counters.fetched_bytes;
+                let download_speed = previous.get(&address).and_then(|(bytes, at)| {
+                    let elapsed = now.duration_since(*at).as_secs_f64();
+                    (elapsed > 0.0).then(|| (fetched_bytes.saturating_sub(*bytes) as f64 / elapsed) as u64)
+                });
+                samples.insert((id, address.clone()), (fetched_bytes, now));
+
+                peers.push(PeerInfo {
+                    address,
+                    state: stats.state.to_string(),
+                    download_speed,
+                    fetched_bytes,
+                    connection_attempts: stats.counters.connection_attempts,
+                    errors: stats.counters.errors,
+                });
+            }
+        }
+        debug!("Torrent {} has {} peers", id, peers.len());
+
+        // Get the trackers the session announces the torrent to
+        let trackers = handle.info().trackers.iter().map(|url| url.to_string()).collect();
+
+        // Get the state of the DHT of the session
+        let dht = match self.session.get_dht() {
+            Some(dht) => {
+                let stats = dht.stats();
+                DhtInfo {
+                    enabled: true,
+                    routing_table_size: stats.routing_table_size,
+                    outstanding_requests: stats.outstanding_requests,
+                }
+            }
+            None => DhtInfo { enabled: false, routing_table_size: 0, outstanding_requests: 0 },
+        };
+
+        Ok(DownloadInspection { id, peers, trackers, dht })
+    }
+
+    /// Function to get the network state of the session.
+    ///
+    /// # Arguments
+    /// * `options` - The configured network options, reported next to the state.
+    ///
+    pub fn network_status(&self, options: NetworkOptions) -> NetworkStatus {
+        NetworkStatus {
+            options,
+            active_port: self.session.tcp_listen_port(),
+            dht_running: self.session.get_dht().is_some(),
+        }
+    }
+
+    /// Function to change the global bandwidth limits of the session.
+    /// It will be called when the user updates the limits from the settings page.
+    ///
+    /// # Arguments
+    /// * `limits` - The new global bandwidth limits.
+    ///
+    pub fn set_global_limits(&self, limits: BandwidthLimits) {
+        trace!("Setting global limits: {:?}", limits);
+        let config: LimitsConfig = limits.into();
+        self.session.ratelimits.set_download_bps(config.download_bps);
+        self.session.ratelimits.set_upload_bps(config.upload_bps);
+    }
+
+    /// Function to change the bandwidth limits of a single download.
+    ///
+    /// # Arguments
+    /// * `id` - The id of the download in the downloads table.
+    /// * `limits` - The new bandwidth limits of the download.
+    ///
+    pub fn set_download_limits(&self, id: i64, limits: BandwidthLimits) -> Result<(), anyhow::Error> {
+        trace!("Setting limits of torrent {}: {:?}", id, limits);
+        let handle = get_handle(id)?;
+        let config: LimitsConfig = limits.into();
+        handle.ratelimits.set_download_bps(config.download_bps);
+        handle.ratelimits.set_upload_bps(config.upload_bps);
+
+        Ok(())
+    }
+
+    /// Function to stop the session and all the torrents attached to it.
+    /// It will be called when the application exits.
+    pub async fn stop(&self) {
+        trace!("Stopping session");
+        self.session.stop().await;
+        trace!("Session stopped");
+    }
+}
+
+/// Peer of a download, as reported by librqbit.
+/// The download speed is in bytes per second, it is measured between two inspections of the download.
+/// librqbit 7 does not report the client, the upload speed or the progress of a peer, so they are not part of it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerInfo {
+    pub address: String,
+    pub state: String,
+    pub download_speed: Option<u64>,
+    pub fetched_bytes: u64,
+    pub connection_attempts: u32,
+    pub errors: u32,
+}
+
+/// State of the DHT of the session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DhtInfo {
+    pub enabled: bool,
+    pub routing_table_size: usize,
+    pub outstanding_requests: usize,
 }
 
-/// Function to get the status of all the torrents.
+/// Peers, trackers and DHT state of a download.
+/// It will be used to understand why a download is stalled.
+/// The trackers are the ones the torrent was added with, librqbit 7 does not report the result of each announce.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadInspection {
+    pub id: i64,
+    pub peers: Vec<PeerInfo>,
+    pub trackers: Vec<String>,
+    pub dht: DhtInfo,
+}
+
+/// Progress of a single download.
+/// It is sent to the frontend with the download-progress event.
+/// Speeds are in bytes per second, the ETA and the seeding time are in seconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadProgress {
+    pub id: i64,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub download_speed: u64,
+    pub upload_speed: u64,
+    pub peers: usize,
+    pub eta: Option<u64>,
+    pub state: String,
+    pub ratio: f64,
+    pub seeding_time: Option<u64>,
+}
+
+impl DownloadProgress {
+
+    /// Function to build the progress of a download from the stats of its handle.
+    ///
+    /// # Arguments
+    /// * `id` - The id of the download in the downloads table.
+    /// * `handle` - The handle of the torrent in the session.
+    ///
+    fn from_handle(id: i64, handle: &ManagedTorrent) -> Self {
+        let stats = handle.stats();
+
+        // Speeds are reported by librqbit in MiB/s
+        let (download_speed, upload_speed, peers) = match &stats.live {
+            Some(live) => (
+                (live.download_speed.mbps * 1024.0 * 1024.0) as u64,
+                (live.upload_speed.mbps * 1024.0 * 1024.0) as u64,
+                live.snapshot.peer_stats.live,
+            ),
+            None => (0, 0, 0),
+        };
+
+        // Estimate the remaining time from the current download speed
+        let eta = if stats.finished {
+            Some(0)
+        } else if download_speed > 0 {
+            Some(stats.total_bytes.saturating_sub(stats.progress_bytes) / download_speed)
+        } else {
+            None
+        };
+
+        let state = match stats.state {
+            TorrentStatsState::Initializing => "initializing",
+            TorrentStatsState::Paused if stats.finished => "completed",
+            TorrentStatsState::Paused => "paused",
+            TorrentStatsState::Error => "error",
+            TorrentStatsState::Live if stats.finished => "seeding",
+            TorrentStatsState::Live => "downloading",
+        };
+
+        // Get how much the torrent has seeded, the previous runs are only known while it seeds
+        let seeding = SEEDING.lock().ok().and_then(|seeding| seeding.get(&id).copied());
+        let uploaded_bytes = match seeding {
+            Some(seeding) => seeding.uploaded_bytes(handle),
+            None => stats.uploaded_bytes,
+        };
+        let seeding_time = seeding.map(|seeding| seeding.elapsed().as_secs());
+
+        Self {
+            id,
+            bytes_done: stats.progress_bytes,
+            bytes_total: stats.total_bytes,
+            download_speed,
+            upload_speed,
+            peers,
+            eta,
+            state: state.to_string(),
+            ratio: share_ratio(handle, uploaded_bytes),
+            seeding_time,
+        }
+    }
+}
+
+/// Function to get the progress of all the torrents.
 /// It will be recalled when the user clicks on the TODO page.
 /// 
 /// Flow:
-///* `Get the global list of handles
+///* `Get the global map of handles
 ///* `Create the result vector
 ///* `Iterate over all the handles
-///* `Get the status of the handle
-///* `Push the status to the result vector
+///* `Get the progress of the handle
+///* `Push the progress to the result vector
 /// 
 /// # Returns
-/// A vector of strings that holds the status of all the torrents.
+/// A vector that holds the progress of all the torrents.
 /// 
-pub async fn get_torrent_statuses() -> Vec<std::string::String> {
+pub async fn get_download_progress() -> Vec<DownloadProgress> {
 
-    // Get the global list of handles
-    trace!("Getting list of handles");
-    let handles = HANDLES.lock().unwrap();
+    // Get the global map of handles
+    trace!("Getting map of handles");
+    let handles = match HANDLES.lock() {
+        Ok(h) => h,
+        Err(e) => {
+            error!("error locking global handles: {:?}", e);
+            return Vec::new();
+        }
+    };
 
     // Create the result vector
-    let mut statuses = Vec::new();
-
+    let mut progress = Vec::new();
 
     // Iterate over all the handles
-    for handle in handles.iter() {
-
-        // Get the status of the handle
-        let status = handle.stats();
+    for (id, handle) in handles.iter() {
 
-        // Push the status to the result vector
-        statuses.push(status.to_string());
+        // Push the progress of the handle to the result vector
+        progress.push(DownloadProgress::from_handle(*id, handle));
     }
 
-    print!("Number of torrents: {}", statuses.len());
-    debug!("Number of torrents: {}", statuses.len());
+    debug!("Number of torrents: {}", progress.len());
 
     // Return the result vector
-    statuses
+    progress
+}
+
+/// Function to push the progress of all the torrents to the frontend.
+/// It runs as a background task for the whole life of the application,
+/// emits a download-progress event every PROGRESS_INTERVAL
+/// and saves the size and the downloaded bytes in the database every SAVE_PROGRESS_INTERVAL.
+///
+/// # Arguments
+/// * `app_handle` - The handle of the Tauri application used to emit the events, it also holds the database.
+///
+pub async fn emit_progress(app_handle: tauri::AppHandle) {
+    let db = app_handle.state::<Database>().inner().clone();
+    let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+    let mut last_saved = Instant::now();
+
+    loop {
+        interval.tick().await;
+
+        let progress = get_download_progress().await;
+        if let Err(e) = app_handle.emit_all("download-progress", &progress) {
+            error!("error emitting download progress: {:?}", e);
+        }
+
+        // Save the progress of the downloads
+        if last_saved.elapsed() >= SAVE_PROGRESS_INTERVAL {
+            last_saved = Instant::now();
+            for download in &progress {
+                if let Err(e) = db.set_download_progress(download.id, download.bytes_total, download.bytes_done).await {
+                    error!("error saving progress of download {}: {:?}", download.id, e);
+                }
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Function to open a temporary database with a downloading row for the torrent of a test.
+    /// It returns the database and the id of the download.
+    async fn downloading(name: &str, magnet_link: &str) -> (Database, i64) {
+        let path = std::env::temp_dir().join(format!("gameio-torrent-{}-{}.sqlite", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db = Database::open(path.to_str().unwrap()).unwrap();
+        let id = db.add_download("Linux Mint", "Linux Mint", magnet_link, "", "/home/user/Downloads", "8df6e26142615621983763b729f640372cf1fc34", None).await.unwrap();
+        db.set_download_status(id, DownloadStatus::Downloading).await.unwrap();
+        (db, id)
+    }
+
     #[tokio::test]
     async fn test_download_torrent() {
         let magnet_link = "magnet:?xt=urn:btih:8DF6E26142615621983763B729F640372CF1FC34&dn=Linux+Mint+20.1+%26quot%3BUlyssa%26quot%3B+-+Cinnamon+%2864-bit%29&tr=udp%3A%2F%2Ftracker.opentrackr.org%3A1337%2Fannounce&tr=http%3A%2F%2Ftracker.openbittorrent.com%3A80%2Fannounce&tr=udp%3A%2F%2Fopentracker.i2p.rocks%3A6969%2Fannounce&tr=udp%3A%2F%2Ftracker.internetwarriors.net%3A1337%2Fannounce&tr=udp%3A%2F%2Ftracker.leechers-paradise.org%3A6969%2Fannounce&tr=udp%3A%2F%2Fcoppersurfer.tk%3A6969%2Fannounce&tr=udp%3A%2F%2Ftracker.zer0day.to%3A1337%2Fannounce";
-        download_torrent("/home/user/Downloads", magnet_link).await;
+        let (db, id) = downloading("download", magnet_link).await;
+        let manager = TorrentManager::new("/home/user/Downloads", BandwidthLimits::default(), NetworkOptions::default()).await.unwrap();
+        manager.download_torrent(&db, id, "/home/user/Downloads", magnet_link, DownloadOptions::default()).await.unwrap();
 
         // Test will pass if no error is thrown during the download of a torrent via magnetlink
         // This might take a while to complete
@@ -144,13 +1391,75 @@ mod tests {
         assert!(true);
     }
 
+    #[test]
+    fn test_required_space() {
+        let files = vec![
+            TorrentFile { index: 0, name: "setup.exe".to_string(), size: 100 },
+            TorrentFile { index: 1, name: "fg-optional-bonus.bin".to_string(), size: 50 },
+        ];
+
+        assert_eq!(required_space(&files, None, 0), 150);
+        assert_eq!(required_space(&files, Some(&[1]), 0), 50);
+        assert_eq!(required_space(&files, None, 120), 30);
+        assert_eq!(required_space(&files, None, 200), 0);
+    }
+
+    #[test]
+    fn test_insufficient_space_message() {
+        let error = InsufficientSpace { required: 2048, available: 1024 };
+
+        assert_eq!(error.to_string(), "not enough disk space: 2048 bytes required, 1024 bytes available");
+    }
+
+    #[test]
+    fn test_read_torrent_file() {
+        assert!(read_torrent_file(b"not a torrent").is_err());
+
+        let bytes = b"d8:announce19:udp://a:80/announce13:announce-listll19:udp://a:80/announceel19:udp://b:80/announceee4:infod6:lengthi1e4:name4:game12:piece lengthi16384e6:pieces20:xxxxxxxxxxxxxxxxxxxxee";
+        let info = read_torrent_file(bytes).unwrap();
+
+        assert_eq!(info.name, "game");
+        assert_eq!(info.trackers, vec!["udp://a:80/announce", "udp://b:80/announce"]);
+    }
+
+    #[test]
+    fn test_seeding_policy_is_reached() {
+        assert!(SeedingPolicy::Never.is_reached(0.0, Duration::ZERO));
+        assert!(SeedingPolicy::Ratio(1.5).is_reached(1.5, Duration::ZERO));
+        assert!(!SeedingPolicy::Ratio(1.5).is_reached(1.0, Duration::ZERO));
+        assert!(SeedingPolicy::Duration(60).is_reached(0.0, Duration::from_secs(60)));
+        assert!(!SeedingPolicy::Forever.is_reached(100.0, Duration::MAX));
+    }
+
+    #[test]
+    fn test_seeding_policy_from_str() {
+        assert_eq!("never".parse::<SeedingPolicy>().unwrap(), SeedingPolicy::Never);
+        assert_eq!("ratio:1.5".parse::<SeedingPolicy>().unwrap(), SeedingPolicy::Ratio(1.5));
+        assert_eq!("duration:3600".parse::<SeedingPolicy>().unwrap(), SeedingPolicy::Duration(3600));
+        assert_eq!(SeedingPolicy::Ratio(2.0).to_string().parse::<SeedingPolicy>().unwrap(), SeedingPolicy::Ratio(2.0));
+        assert!("ratio:abc".parse::<SeedingPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_listen_port_range() {
+        let options = |start, end| NetworkOptions { listen_port_start: start, listen_port_end: end, dht: true };
+
+        assert_eq!(options(6881, 6889).listen_port_range(), Some(6881..6890));
+        assert_eq!(options(6881, 6881).listen_port_range(), Some(6881..6882));
+        assert_eq!(options(0, 6889).listen_port_range(), None);
+        assert_eq!(options(6889, 6881).listen_port_range(), None);
+        assert_eq!(options(65535, 65535).listen_port_range(), None);
+    }
+
     #[tokio::test]
-    async fn test_get_torrent_statuses() {
+    async fn test_get_download_progress() {
         let magnet_link = "magnet:?xt=urn:btih:8DF6E26142615621983763B729F640372CF1FC34&dn=Linux+Mint+20.1+%26quot%3BUlyssa%26quot%3B+-+Cinnamon+%2864-bit%29&tr=udp%3A%2F%2Ftracker.opentrackr.org%3A1337%2Fannounce&tr=http%3A%2F%2Ftracker.openbittorrent.com%3A80%2Fannounce&tr=udp%3A%2F%2Fopentracker.i2p.rocks%3A6969%2Fannounce&tr=udp%3A%2F%2Ftracker.internetwarriors.net%3A1337%2Fannounce&tr=udp%3A%2F%2Ftracker.leechers-paradise.org%3A6969%2Fannounce&tr=udp%3A%2F%2Fcoppersurfer.tk%3A6969%2Fannounce&tr=udp%3A%2F%2Ftracker.zer0day.to%3A1337%2Fannounce";
-        download_torrent("/home/user/Downloads", magnet_link).await;
+        let (db, id) = downloading("progress", magnet_link).await;
+        let manager = TorrentManager::new("/home/user/Downloads", BandwidthLimits::default(), NetworkOptions::default()).await.unwrap();
+        manager.download_torrent(&db, id, "/home/user/Downloads", magnet_link, DownloadOptions::default()).await.unwrap();
 
-        let statuses = get_torrent_statuses().await;
+        let progress = get_download_progress().await;
 
-        assert!(statuses.len() == 1)
+        assert!(progress.len() == 1)
     }
 }
\ No newline at end of file